@@ -3,25 +3,131 @@ use std::{
     sync::{Mutex, MutexGuard},
 };
 
-use crate::{GameRule, Last50, SideToMove, TurnEndEvent};
+#[cfg(not(target_arch = "wasm32"))]
+pub mod uci;
+
+use crate::{
+    game::{material, Board},
+    variant::{Position, Variant},
+    GameRule, Last50, SideToMove, TurnEndEvent,
+};
 use bevy::prelude::*;
-use chess::{MoveGen, Piece};
+use chess::Piece;
 use futures::FutureExt;
 use miniserde::{Deserialize, Serialize};
 use rand::seq::IteratorRandom;
 
 static NETWORK_MOVE: Mutex<NetworkGetMove> = Mutex::new(NetworkGetMove::Idle);
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engine {
+    StockfishOnline,
+    /// The path to a UCI engine run on this machine
+    Uci(String),
+}
+
+impl Engine {
+    /// The engine as named in the PGN player tags
+    pub fn name(&self) -> String {
+        match self {
+            Engine::StockfishOnline => String::from("Stockfish Online"),
+            Engine::Uci(path) => std::path::Path::new(path)
+                .file_stem()
+                .map_or(path.clone(), |stem| stem.to_string_lossy().into_owned()),
+        }
+    }
+}
+
 #[derive(Resource)]
 pub enum NetworkGetMove {
     Idle,
     Pending,
     Ready(NetworkMove),
+    /// The engine couldn't be run or didn't answer with a move
+    Failed(String),
 }
 
 pub struct NetworkMove {
     from: chess::Square,
     to: chess::Square,
+    promotion: Option<Piece>,
+    /// The engine's evaluation of the position it moved from, in centipawns for White
+    evaluation: Option<i32>,
+}
+
+impl NetworkMove {
+    fn from_uci(uci: &str) -> Option<NetworkMove> {
+        parse_uci(uci).map(|m| NetworkMove {
+            from: m.get_source(),
+            to: m.get_dest(),
+            promotion: m.get_promotion(),
+            evaluation: None,
+        })
+    }
+}
+
+/// Forced mates are scored beyond any material advantage
+const MATE_SCORE: i32 = 10_000;
+
+/// The last evaluation reported by the engine, along with the position it evaluated
+#[derive(Resource, Default)]
+pub struct Evaluation(Option<(Position, i32)>);
+
+impl Evaluation {
+    /// The evaluation in centipawns for White, if it was made during the game on `board`
+    pub fn in_game(&self, board: &Board) -> Option<i32> {
+        let (position, centipawns) = self.0?;
+        let tree = board.tree();
+        tree.path(board.live())
+            .into_iter()
+            .any(|id| tree.node(id).board == position)
+            .then_some(centipawns)
+    }
+}
+
+/// Whether the AI playing `color` accepts a draw offered in the position on `board`
+///
+/// The engine's last evaluation decides, with the material balance standing in
+/// before it has evaluated anything. A clearly worse position is always drawn, an
+/// even one only once most pieces are off the board, and nothing else in the opening.
+pub fn accepts_draw(board: &Board, evaluation: Option<i32>, color: chess::Color) -> bool {
+    // offers can be made while reviewing, but it's the live position that counts
+    let live = &board.tree().node(board.live()).board;
+    let for_white = evaluation.unwrap_or_else(|| material::balance(live) * 100);
+    let score = match color {
+        chess::Color::White => for_white,
+        chess::Color::Black => -for_white,
+    };
+    if score <= -150 {
+        return true;
+    }
+    if board.tree().ply(board.live()) < 30 {
+        return false;
+    }
+    if material::phase(live) <= 26 {
+        score <= 50
+    } else {
+        score <= -50
+    }
+}
+
+/// Parses a move in UCI notation, such as `e2e4` or `e7e8q`
+///
+/// Antichess promotions to a king end in `k`, and Crazyhouse drops are read back
+/// as written by `ChessMove`'s `Display`, such as `e4e4n`.
+pub fn parse_uci(uci: &str) -> Option<chess::ChessMove> {
+    let from = chess::Square::from_str(uci.get(0..2)?).ok()?;
+    let to = chess::Square::from_str(uci.get(2..4)?).ok()?;
+    let promotion = match uci.get(4..5) {
+        Some("q") => Some(Piece::Queen),
+        Some("r") => Some(Piece::Rook),
+        Some("b") => Some(Piece::Bishop),
+        Some("n") => Some(Piece::Knight),
+        Some("k") => Some(Piece::King),
+        Some("p") => Some(Piece::Pawn),
+        _ => None,
+    };
+    Some(chess::ChessMove::new(from, to, promotion))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -36,9 +142,14 @@ struct StockfishOnlineResponse {
 impl StockfishOnlineResponse {
     fn into_network_move(self) -> NetworkMove {
         let move_san = self.bestmove.split(" ").nth(1).unwrap();
-        let from = chess::Square::from_str(&move_san[0..2]).unwrap();
-        let to = chess::Square::from_str(&move_san[2..4]).unwrap();
-        NetworkMove { from, to }
+        let evaluation = match self.mate {
+            Some(moves) => moves.signum() * MATE_SCORE,
+            None => (self.evaluation * 100.).round() as i32,
+        };
+        NetworkMove {
+            evaluation: Some(evaluation),
+            ..NetworkMove::from_uci(move_san).unwrap()
+        }
     }
 }
 
@@ -48,31 +159,78 @@ pub fn stockfish_move(
     game_rule: Res<GameRule>,
     mut board: ResMut<crate::game::Board>,
     mut last_50: ResMut<Last50>,
+    mut evaluation: ResMut<Evaluation>,
 ) {
-    if side_to_move.0 != game_rule.ai_color {
+    // a reply that arrives during review waits until the game is back to live
+    if side_to_move.0 != game_rule.ai_color || board.is_reviewing() {
         return;
     }
 
     let mut network_get_move = NETWORK_MOVE.lock().expect("Mutex should never be poisoned");
 
-    match *network_get_move {
+    let mut rng = rand::thread_rng();
+    let m = match *network_get_move {
+        // engines can't read positions missing a king, which happen in Antichess
+        NetworkGetMove::Idle if board.to_board().is_none() => {
+            let m = board.legal_moves().into_iter().choose(&mut rng).unwrap();
+            *network_get_move = NetworkGetMove::Ready(NetworkMove {
+                from: m.get_source(),
+                to: m.get_dest(),
+                promotion: m.get_promotion(),
+                evaluation: None,
+            });
+            return;
+        }
         NetworkGetMove::Idle => {
-            request_network_move(board.to_string(), network_get_move);
+            let tree = board.tree();
+            let fen = tree.engine_fen(tree.current());
+            match &game_rule.engine {
+                Engine::StockfishOnline => request_network_move(fen, network_get_move),
+                #[cfg(not(target_arch = "wasm32"))]
+                Engine::Uci(path) => uci::request_uci_move(
+                    path.clone(),
+                    fen,
+                    tree.variant() == Variant::Chess960,
+                    network_get_move,
+                ),
+                // engines can't be started from the browser
+                #[cfg(target_arch = "wasm32")]
+                Engine::Uci(_) => request_network_move(fen, network_get_move),
+            }
+            return;
+        }
+        NetworkGetMove::Pending => return,
+        NetworkGetMove::Failed(ref e) => {
+            warn!("{e}, playing a random move instead");
+            board.legal_moves().into_iter().choose(&mut rng).unwrap()
         }
-        NetworkGetMove::Pending => {}
         NetworkGetMove::Ready(ref network_move) => {
             // assume that the network move is well-formed
-            let m = chess::ChessMove::new(network_move.from, network_move.to, None);
-            let p = board.piece_on(m.get_source()).unwrap();
-            let t = board.piece_on(m.get_dest());
-            **board = board.make_move_new(m);
-            side_to_move.0 = !side_to_move.0;
-
-            up_ev.send(TurnEndEvent);
-            last_50.push(p == Piece::Pawn || t.is_some());
-            *network_get_move = NetworkGetMove::Idle;
+            let m = board
+                .castle_move(network_move.from, network_move.to)
+                .unwrap_or(chess::ChessMove::new(
+                    network_move.from,
+                    network_move.to,
+                    network_move.promotion,
+                ));
+            if let Some(centipawns) = network_move.evaluation {
+                evaluation.0 = Some((**board, centipawns));
+            }
+            // the engines only know standard chess and may suggest moves a variant forbids
+            if board.is_legal(m) {
+                m
+            } else {
+                warn!("Engine move {} is illegal in {}", m, board.variant().name());
+                board.legal_moves().into_iter().choose(&mut rng).unwrap()
+            }
         }
-    }
+    };
+    last_50.push(board.resets_fifty_moves(m));
+    board.play(m);
+    side_to_move.0 = !side_to_move.0;
+
+    up_ev.send(TurnEndEvent);
+    *network_get_move = NetworkGetMove::Idle;
 }
 
 pub fn request_network_move(fen: String, mut mutex_guard: MutexGuard<'_, NetworkGetMove>) {
@@ -106,19 +264,16 @@ pub fn single_ai_move(
     mut board: ResMut<crate::game::Board>,
     mut last_50: ResMut<Last50>,
 ) {
-    if side_to_move.0 != game_rule.ai_color {
+    if side_to_move.0 != game_rule.ai_color || board.is_reviewing() {
         return;
     }
-    let move_gen = MoveGen::new_legal(board.as_ref());
     let mut rng = rand::thread_rng();
-    let m = move_gen.into_iter().choose(&mut rng).unwrap();
-    let p = board.piece_on(m.get_source()).unwrap();
-    let t = board.piece_on(m.get_dest());
-    **board = board.make_move_new(m);
+    let m = board.legal_moves().into_iter().choose(&mut rng).unwrap();
+    last_50.push(board.resets_fifty_moves(m));
+    board.play(m);
     side_to_move.0 = !side_to_move.0;
 
     up_ev.send(TurnEndEvent);
-    last_50.push(p == Piece::Pawn || t.is_some());
 }
 
 pub fn sim_ai_move(
@@ -127,14 +282,14 @@ pub fn sim_ai_move(
     mut board: ResMut<crate::game::Board>,
     mut last_50: ResMut<Last50>,
 ) {
-    let move_gen = MoveGen::new_legal(board.as_ref());
+    if board.is_reviewing() {
+        return;
+    }
     let mut rng = rand::thread_rng();
-    let m = move_gen.into_iter().choose(&mut rng).unwrap();
-    let p = board.piece_on(m.get_source()).unwrap();
-    let t = board.piece_on(m.get_dest());
+    let m = board.legal_moves().into_iter().choose(&mut rng).unwrap();
+    last_50.push(board.resets_fifty_moves(m));
 
-    **board = board.make_move_new(m);
+    board.play(m);
     side_to_move.0 = !side_to_move.0;
     up_ev.send(TurnEndEvent);
-    last_50.push(p == Piece::Pawn || t.is_some());
 }
@@ -0,0 +1,141 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Command, Stdio},
+    sync::MutexGuard,
+};
+
+use anyhow::{bail, Context, Result};
+use bevy::prelude::*;
+
+use super::{NetworkGetMove, NetworkMove, MATE_SCORE, NETWORK_MOVE};
+
+const SEARCH_DEPTH: u32 = 12;
+
+/// Asks a local UCI engine for a move in the background
+///
+/// A fresh engine process is started for every request, mirroring the stateless
+/// requests made to the online engine.
+pub fn request_uci_move(
+    path: String,
+    fen: String,
+    chess960: bool,
+    mut mutex_guard: MutexGuard<'_, NetworkGetMove>,
+) {
+    *mutex_guard = NetworkGetMove::Pending;
+    drop(mutex_guard);
+    std::thread::spawn(move || search(&path, &fen, chess960));
+}
+
+/// Runs the engine and hands over its move, or why there isn't one
+fn search(path: &str, fen: &str, chess960: bool) {
+    let result = match best_move(path, fen, chess960) {
+        Ok(network_move) => NetworkGetMove::Ready(network_move),
+        Err(e) => {
+            error!("UCI engine failed: {e:?}");
+            NetworkGetMove::Failed(format!("UCI engine failed: {e}"))
+        }
+    };
+    *NETWORK_MOVE.lock().unwrap() = result;
+}
+
+fn best_move(path: &str, fen: &str, chess960: bool) -> Result<NetworkMove> {
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("couldn't start engine at {}", path))?;
+    let mut stdin = child.stdin.take().context("engine stdin should be piped")?;
+    let mut stdout = BufReader::new(
+        child
+            .stdout
+            .take()
+            .context("engine stdout should be piped")?,
+    );
+
+    writeln!(stdin, "uci")?;
+    wait_for(&mut stdout, "uciok")?;
+    if chess960 {
+        writeln!(stdin, "setoption name UCI_Chess960 value true")?;
+    }
+    writeln!(stdin, "isready")?;
+    wait_for(&mut stdout, "readyok")?;
+    writeln!(stdin, "position fen {}", fen)?;
+    writeln!(stdin, "go depth {}", SEARCH_DEPTH)?;
+    // the last score reported before the best move is the evaluation of the search
+    let mut score = None;
+    let line = loop {
+        let line = wait_for(&mut stdout, "")?;
+        if line.starts_with("bestmove") {
+            break line;
+        }
+        score = parse_score(&line).or(score);
+    };
+    writeln!(stdin, "quit")?;
+    let _ = child.wait();
+
+    let uci = line
+        .split_whitespace()
+        .nth(1)
+        .context("bestmove should be followed by a move")?;
+    let network_move =
+        NetworkMove::from_uci(uci).with_context(|| format!("invalid move from engine: {}", uci))?;
+    // scores are given for the side to move
+    let black_to_move = fen.split_whitespace().nth(1) == Some("b");
+    Ok(NetworkMove {
+        evaluation: score.map(|score| if black_to_move { -score } else { score }),
+        ..network_move
+    })
+}
+
+/// Reads the score of an `info` line, in centipawns
+fn parse_score(line: &str) -> Option<i32> {
+    let mut words = line.split_whitespace().skip_while(|&word| word != "score");
+    words.next()?;
+    match (words.next()?, words.next()?.parse::<i32>().ok()?) {
+        ("cp", centipawns) => Some(centipawns),
+        ("mate", moves) => Some(moves.signum() * MATE_SCORE),
+        _ => None,
+    }
+}
+
+/// Reads lines until one starts with `prefix`, returning that line
+fn wait_for(reader: &mut impl BufRead, prefix: &str) -> Result<String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("engine exited before sending {}", prefix);
+        }
+        if line.starts_with(prefix) {
+            return Ok(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn missing_engine_fails() {
+        assert!(best_move("/nonexistent/engine", START, false).is_err());
+
+        *NETWORK_MOVE.lock().unwrap() = NetworkGetMove::Pending;
+        search("/nonexistent/engine", START, false);
+        assert!(matches!(
+            *NETWORK_MOVE.lock().unwrap(),
+            NetworkGetMove::Failed(_)
+        ));
+        *NETWORK_MOVE.lock().unwrap() = NetworkGetMove::Idle;
+    }
+
+    #[test]
+    fn scores_are_read_from_info_lines() {
+        let line = "info depth 12 seldepth 18 score cp -35 nodes 1000 pv e7e5";
+        assert_eq!(parse_score(line), Some(-35));
+        assert_eq!(parse_score("info depth 5 score mate -3"), Some(-MATE_SCORE));
+        assert_eq!(parse_score("info string hello"), None);
+    }
+}
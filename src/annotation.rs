@@ -0,0 +1,124 @@
+//! Arrows and circles drawn on the board with the right mouse button
+//!
+//! Dragging draws an arrow and clicking circles a square, in green, or in red,
+//! blue or yellow with Shift, Alt or both held, as on Lichess. Drawing the same
+//! shape again erases it.
+
+use bevy::prelude::*;
+use chess::Square;
+
+use crate::game::{Board, PointedSquare};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeColor {
+    Green,
+    Red,
+    Blue,
+    Yellow,
+}
+
+impl ShapeColor {
+    /// The color picked by the modifier keys being held
+    pub fn from_modifiers(keys: &ButtonInput<KeyCode>) -> ShapeColor {
+        let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        // Ctrl stands in for Alt, which some window managers keep for themselves
+        let alt = keys.any_pressed([
+            KeyCode::AltLeft,
+            KeyCode::AltRight,
+            KeyCode::ControlLeft,
+            KeyCode::ControlRight,
+        ]);
+        match (shift, alt) {
+            (false, false) => ShapeColor::Green,
+            (true, false) => ShapeColor::Red,
+            (false, true) => ShapeColor::Blue,
+            (true, true) => ShapeColor::Yellow,
+        }
+    }
+
+    /// The letter used for the color in `[%cal]` and `[%csl]` commands
+    pub fn pgn_char(self) -> char {
+        match self {
+            ShapeColor::Green => 'G',
+            ShapeColor::Red => 'R',
+            ShapeColor::Blue => 'B',
+            ShapeColor::Yellow => 'Y',
+        }
+    }
+
+    pub fn hex(self) -> &'static str {
+        match self {
+            ShapeColor::Green => "#15781BCC", // lichess colors
+            ShapeColor::Red => "#882020CC",
+            ShapeColor::Blue => "#003088CC",
+            ShapeColor::Yellow => "#E68F00CC",
+        }
+    }
+}
+
+/// An arrow between two squares, or a circle if both are the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shape {
+    pub from: Square,
+    pub to: Square,
+    pub color: ShapeColor,
+}
+
+impl Shape {
+    pub fn is_circle(&self) -> bool {
+        self.from == self.to
+    }
+}
+
+/// The shapes drawn on the position shown, which is cleared by the next move
+///
+/// The shapes are also kept on the position's node in the game tree, so they come
+/// back when stepping through the game and end up in the exported PGN.
+#[derive(Resource, Default)]
+pub struct Annotations {
+    pub shapes: Vec<Shape>,
+    /// The square a right-click drag started on and the color it will draw in
+    pub drawing: Option<(Square, ShapeColor)>,
+}
+
+impl Annotations {
+    /// Adds `shape`, or erases it if it's already drawn in the same color
+    ///
+    /// A shape drawn in another color is recolored.
+    fn toggle(&mut self, shape: Shape) {
+        let same_place = |s: &Shape| s.from == shape.from && s.to == shape.to;
+        match self.shapes.iter().position(same_place) {
+            Some(i) if self.shapes[i].color == shape.color => {
+                self.shapes.remove(i);
+            }
+            Some(i) => self.shapes[i] = shape,
+            None => self.shapes.push(shape),
+        }
+    }
+}
+
+/// Draws shapes with the right mouse button and swaps them out as the position changes
+pub fn annotate(
+    mouse: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    pointed_square: Res<PointedSquare>,
+    mut board: ResMut<Board>,
+    mut annotations: ResMut<Annotations>,
+) {
+    // moves, resets and steps through the game all change the board
+    if board.is_changed() {
+        annotations.shapes = board.tree().current_node().shapes.clone();
+    }
+
+    if mouse.just_pressed(MouseButton::Right) {
+        annotations.drawing =
+            pointed_square.map(|square| (square, ShapeColor::from_modifiers(&keys)));
+    }
+    if mouse.just_released(MouseButton::Right) {
+        let (Some((from, color)), Some(to)) = (annotations.drawing.take(), **pointed_square) else {
+            return;
+        };
+        annotations.toggle(Shape { from, to, color });
+        board.tree_mut().current_node_mut().shapes = annotations.shapes.clone();
+    }
+}
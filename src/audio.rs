@@ -0,0 +1,131 @@
+//! Sound effects for moves and the end of the game
+//!
+//! The sounds are short WAV files in `assets/sounds/`, which Bevy plays through
+//! Web Audio on the wasm build. Browsers only allow audio after the first click,
+//! which always comes before the first move.
+
+use bevy::{audio::Volume, prelude::*};
+
+use crate::game::{tree::NodeId, Board};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    Move,
+    Capture,
+    Castle,
+    Check,
+    Promotion,
+    /// A move the rules don't allow was tried
+    Illegal,
+    GameEnd,
+}
+
+impl Sound {
+    const ALL: [Sound; 7] = [
+        Sound::Move,
+        Sound::Capture,
+        Sound::Castle,
+        Sound::Check,
+        Sound::Promotion,
+        Sound::Illegal,
+        Sound::GameEnd,
+    ];
+
+    fn path(self) -> &'static str {
+        match self {
+            Sound::Move => "sounds/move.wav",
+            Sound::Capture => "sounds/capture.wav",
+            Sound::Castle => "sounds/castle.wav",
+            Sound::Check => "sounds/check.wav",
+            Sound::Promotion => "sounds/promotion.wav",
+            Sound::Illegal => "sounds/illegal.wav",
+            Sound::GameEnd => "sounds/game_end.wav",
+        }
+    }
+
+    /// The sound for the move written as `san`, with checks taking precedence
+    fn for_move(san: &str) -> Sound {
+        if san.ends_with('+') || san.ends_with('#') {
+            Sound::Check
+        } else if san.contains('=') {
+            Sound::Promotion
+        } else if san.starts_with("O-O") {
+            Sound::Castle
+        } else if san.contains('x') {
+            Sound::Capture
+        } else {
+            Sound::Move
+        }
+    }
+}
+
+#[derive(Event)]
+pub struct PlaySound(pub Sound);
+
+#[derive(Resource, Debug)]
+pub struct AudioSettings {
+    /// From 0 for silent to 1 for the volume the sounds were recorded at
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            volume: 0.7,
+            muted: false,
+        }
+    }
+}
+
+/// The loaded sounds, indexed like `Sound::ALL`
+#[derive(Resource)]
+pub struct Sounds([Handle<AudioSource>; Sound::ALL.len()]);
+
+pub fn load_sounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Sounds(
+        Sound::ALL.map(|sound| asset_server.load(sound.path())),
+    ));
+}
+
+/// Picks the sound for each move as it is played
+pub fn move_sounds(
+    board: Res<Board>,
+    mut sound_ev: EventWriter<PlaySound>,
+    mut last_node: Local<Option<NodeId>>,
+) {
+    let tree = board.tree();
+    let current = tree.current();
+    let previous = last_node.replace(current);
+    if previous == Some(current) {
+        return;
+    }
+    // jumps through the game stay quiet, like they aren't animated either
+    let node = tree.current_node();
+    if node.parent.is_none() || node.parent != previous {
+        return;
+    }
+    sound_ev.send(PlaySound(Sound::for_move(&node.san)));
+}
+
+pub fn game_end_sound(mut sound_ev: EventWriter<PlaySound>) {
+    sound_ev.send(PlaySound(Sound::GameEnd));
+}
+
+pub fn play_sounds(
+    mut commands: Commands,
+    mut sound_ev: EventReader<PlaySound>,
+    sounds: Res<Sounds>,
+    settings: Res<AudioSettings>,
+) {
+    for PlaySound(sound) in sound_ev.read() {
+        if settings.muted || settings.volume <= 0. {
+            continue;
+        }
+        let index = Sound::ALL.iter().position(|s| s == sound).unwrap();
+        commands.spawn((
+            AudioPlayer::new(sounds.0[index].clone()),
+            PlaybackSettings::DESPAWN.with_volume(Volume::new(settings.volume)),
+        ));
+    }
+}
@@ -0,0 +1,355 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_svg::prelude::*;
+use chess::{BoardBuilder, CastleRights, Color, File, Piece, Rank, Square, ALL_PIECES};
+
+use crate::{
+    game::{cursor_world_position, PointedSquare},
+    render::{DrawInfo, BOARD_LENGTH, SPRITE_SIZE},
+    theme::Theme,
+};
+
+/// A piece placed by the board editor
+#[derive(Component)]
+pub struct EditorPiece;
+
+/// One of the twelve pieces in the editor palette
+#[derive(Component)]
+pub struct PaletteSlot;
+
+/// The piece currently being dragged around in the editor
+#[derive(Component)]
+pub struct HeldPiece;
+
+/// A position being set up in the board editor
+///
+/// The position may be invalid while it is edited; it is only checked when
+/// a game is started from it.
+#[derive(Resource)]
+pub struct Editor {
+    pub builder: BoardBuilder,
+    pub side_to_move: Color,
+    /// Kingside and queenside castling rights, indexed by color
+    pub castling: [[bool; 2]; 2],
+    pub en_passant: Option<File>,
+    /// Half moves without a capture or pawn move before the position, from a loaded FEN
+    pub halfmove_clock: usize,
+    /// The move number of the position, from a loaded FEN
+    pub fullmove_number: usize,
+    /// The palette piece placed by clicking on a square
+    pub brush: Option<(Piece, Color)>,
+    /// The piece being dragged, either out of the palette or off of a square
+    pub held: Option<(Piece, Color)>,
+    pub fen: String,
+    pub error: Option<String>,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        let mut editor = Self {
+            builder: BoardBuilder::new(),
+            side_to_move: Color::White,
+            castling: [[false; 2]; 2],
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            brush: None,
+            held: None,
+            fen: String::new(),
+            error: None,
+        };
+        editor.load(&chess::Board::default());
+        editor
+    }
+}
+
+impl Editor {
+    /// Replaces the edited position with `board`
+    pub fn load(&mut self, board: &chess::Board) {
+        self.builder = BoardBuilder::from(board);
+        self.side_to_move = board.side_to_move();
+        for color in chess::ALL_COLORS {
+            let rights = board.castle_rights(color);
+            self.castling[color.to_index()] = [rights.has_kingside(), rights.has_queenside()];
+        }
+        self.en_passant = board.en_passant().map(|square| square.get_file());
+        self.halfmove_clock = 0;
+        self.fullmove_number = 1;
+        self.fen = board.to_string();
+        self.error = None;
+    }
+
+    /// Removes every piece and all castling rights
+    pub fn clear(&mut self) {
+        self.builder = BoardBuilder::new();
+        self.castling = [[false; 2]; 2];
+        self.en_passant = None;
+        self.halfmove_clock = 0;
+        self.fullmove_number = 1;
+        self.error = None;
+    }
+
+    /// Validates the edited position and turns it into a playable board
+    pub fn build(&self) -> Result<chess::Board, String> {
+        for color in chess::ALL_COLORS {
+            let kings = chess::ALL_SQUARES
+                .iter()
+                .filter(|&&sq| self.builder[sq] == Some((Piece::King, color)))
+                .count();
+            if kings != 1 {
+                return Err(format!("{:?} must have exactly one king", color));
+            }
+        }
+
+        for square in chess::ALL_SQUARES {
+            let rank = square.get_rank();
+            if matches!(self.builder[square], Some((Piece::Pawn, _)))
+                && (rank == Rank::First || rank == Rank::Eighth)
+            {
+                return Err(format!("Pawn on {}", square));
+            }
+        }
+
+        for color in chess::ALL_COLORS {
+            let [kingside, queenside] = self.castling[color.to_index()];
+            let back_rank = color.to_my_backrank();
+            let king_home =
+                self.builder[Square::make_square(back_rank, File::E)] == Some((Piece::King, color));
+            let rook_on = |file| {
+                self.builder[Square::make_square(back_rank, file)] == Some((Piece::Rook, color))
+            };
+            if kingside && !(king_home && rook_on(File::H)) {
+                return Err(format!("{:?} cannot castle kingside", color));
+            }
+            if queenside && !(king_home && rook_on(File::A)) {
+                return Err(format!("{:?} cannot castle queenside", color));
+            }
+        }
+
+        if let Some(file) = self.en_passant {
+            let mover = !self.side_to_move;
+            let pawn = Square::make_square(mover.to_fourth_rank(), file);
+            let skipped = Square::make_square(
+                Rank::from_index(
+                    (mover.to_second_rank().to_index() + pawn.get_rank().to_index()) / 2,
+                ),
+                file,
+            );
+            let start = Square::make_square(mover.to_second_rank(), file);
+            if self.builder[pawn] != Some((Piece::Pawn, mover))
+                || self.builder[skipped].is_some()
+                || self.builder[start].is_some()
+            {
+                return Err(format!(
+                    "No pawn can be captured en passant on the {:?} file",
+                    file
+                ));
+            }
+        }
+
+        let mut builder = self.builder;
+        builder
+            .side_to_move(self.side_to_move)
+            .castle_rights(Color::White, castle_rights(self.castling[0]))
+            .castle_rights(Color::Black, castle_rights(self.castling[1]))
+            .en_passant(self.en_passant);
+        let board = chess::Board::try_from(&builder).map_err(|e| e.to_string())?;
+
+        let their_king = board.king_square(!board.side_to_move());
+        if crate::game::attackers(&board, their_king, board.side_to_move()).popcnt() > 0 {
+            return Err(format!(
+                "{:?} is to move but can capture the king",
+                board.side_to_move()
+            ));
+        }
+        Ok(board)
+    }
+}
+
+fn castle_rights([kingside, queenside]: [bool; 2]) -> CastleRights {
+    match (kingside, queenside) {
+        (false, false) => CastleRights::NoRights,
+        (true, false) => CastleRights::KingSide,
+        (false, true) => CastleRights::QueenSide,
+        (true, true) => CastleRights::Both,
+    }
+}
+
+/// The palette holds white pieces in the outer column and black pieces in the inner one
+fn palette_position(draw_info: &DrawInfo, piece: Piece, color: Color) -> Vec2 {
+    let square_size = draw_info.get_square_size();
+    let column = match color {
+        Color::White => 1.9,
+        Color::Black => 0.9,
+    };
+    let row = 2.5 - piece.to_index() as f32;
+    Vec2::new(
+        -square_size * (BOARD_LENGTH as f32 / 2. + column),
+        square_size * row,
+    )
+}
+
+fn palette_at(draw_info: &DrawInfo, pos: Vec2) -> Option<(Piece, Color)> {
+    let half = draw_info.get_square_size() / 2.;
+    chess::ALL_COLORS
+        .iter()
+        .flat_map(|&color| ALL_PIECES.iter().map(move |&piece| (piece, color)))
+        .find(|&(piece, color)| {
+            let center = palette_position(draw_info, piece, color);
+            (pos.x - center.x).abs() < half && (pos.y - center.y).abs() < half
+        })
+}
+
+pub fn enter_editor(mut editor: ResMut<Editor>) {
+    editor.set_changed();
+}
+
+pub fn exit_editor(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    drawn: Query<Entity, Or<(With<EditorPiece>, With<PaletteSlot>, With<HeldPiece>)>>,
+) {
+    editor.held = None;
+    for e in drawn.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+pub fn editor_input(
+    camera: Query<(&Camera, &GlobalTransform)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    pointed_square: Res<PointedSquare>,
+    input: Res<ButtonInput<MouseButton>>,
+    draw_info: Res<DrawInfo>,
+    mut editor: ResMut<Editor>,
+) {
+    if input.just_pressed(MouseButton::Left) {
+        let palette =
+            cursor_world_position(&camera, &window).and_then(|pos| palette_at(&draw_info, pos));
+        if let Some(slot) = palette {
+            editor.brush = if editor.brush == Some(slot) {
+                None
+            } else {
+                Some(slot)
+            };
+            editor.held = Some(slot);
+        } else if let Some(square) = **pointed_square {
+            match editor.brush {
+                Some(brush) if editor.builder[square] == Some(brush) => {
+                    editor.builder.clear_square(square);
+                }
+                Some((piece, color)) => {
+                    editor.builder.piece(square, piece, color);
+                }
+                None => {
+                    editor.held = editor.builder[square];
+                    editor.builder.clear_square(square);
+                }
+            }
+            editor.error = None;
+        }
+    }
+
+    if input.just_released(MouseButton::Left) {
+        if let Some((piece, color)) = editor.held.take() {
+            if let Some(square) = **pointed_square {
+                editor.builder.piece(square, piece, color);
+                editor.error = None;
+            }
+        }
+    }
+
+    if input.just_pressed(MouseButton::Right) {
+        if let Some(square) = **pointed_square {
+            editor.builder.clear_square(square);
+            editor.error = None;
+        }
+    }
+}
+
+pub fn draw_editor(
+    mut commands: Commands,
+    editor: Res<Editor>,
+    draw_info: Res<DrawInfo>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    drawn: Query<Entity, Or<(With<EditorPiece>, With<PaletteSlot>, With<HeldPiece>)>>,
+) {
+    if !editor.is_changed() && !draw_info.is_changed() && !theme.is_changed() {
+        return;
+    }
+    for e in drawn.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
+    let square_size = draw_info.get_square_size();
+    let scale = Vec3::new(square_size / SPRITE_SIZE, square_size / SPRITE_SIZE, 1.0);
+    let offset = -square_size * BOARD_LENGTH as f32 / 2.;
+
+    for square in chess::ALL_SQUARES {
+        let Some((piece, color)) = editor.builder[square] else {
+            continue;
+        };
+        let file = square.get_file().to_index() as f32;
+        let rank = square.get_rank().to_index() as f32;
+        commands.spawn((
+            Svg2d(asset_server.load(theme.piece_path(piece, color))),
+            Origin::Center,
+            Transform::from_translation(Vec3::new(
+                offset + file * square_size + (square_size / 2.),
+                offset + rank * square_size + (square_size / 2.),
+                3.0,
+            ))
+            .with_scale(scale),
+            EditorPiece,
+        ));
+    }
+
+    for color in chess::ALL_COLORS {
+        for piece in ALL_PIECES {
+            let pos = palette_position(&draw_info, piece, color);
+            if editor.brush == Some((piece, color)) {
+                commands.spawn((
+                    Sprite {
+                        color: Srgba::hex(theme.palette.select).unwrap().into(),
+                        custom_size: Some(Vec2::new(square_size, square_size)),
+                        ..default()
+                    },
+                    Transform::from_translation(pos.extend(2.0)),
+                    PaletteSlot,
+                ));
+            }
+            commands.spawn((
+                Svg2d(asset_server.load(theme.piece_path(piece, color))),
+                Origin::Center,
+                Transform::from_translation(pos.extend(3.0)).with_scale(scale),
+                PaletteSlot,
+            ));
+        }
+    }
+
+    if let Some((piece, color)) = editor.held {
+        commands.spawn((
+            Svg2d(asset_server.load(theme.piece_path(piece, color))),
+            Origin::Center,
+            Transform::from_scale(scale).with_translation(Vec3::new(0.0, 0.0, 4.0)),
+            Visibility::Hidden,
+            HeldPiece,
+        ));
+    }
+}
+
+/// Keeps the dragged piece under the cursor
+pub fn move_held_piece(
+    camera: Query<(&Camera, &GlobalTransform)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut held: Query<(&mut Transform, &mut Visibility), With<HeldPiece>>,
+) {
+    let Some(pos) = cursor_world_position(&camera, &window) else {
+        return;
+    };
+    for (mut transform, mut visibility) in held.iter_mut() {
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+        *visibility = Visibility::Inherited;
+    }
+}
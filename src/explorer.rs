@@ -0,0 +1,228 @@
+//! Opening explorer over a locally imported PGN collection
+//!
+//! Positions are indexed by their Zobrist hash, so transpositions share their
+//! statistics and lookups don't depend on the size of the collection.
+
+use std::{collections::HashMap, io::BufReader, sync::Mutex};
+
+use anyhow::{Context, Result};
+use bevy::prelude::*;
+use chess::ChessMove;
+
+use crate::pgn::{self, PgnGame};
+
+/// Games are only followed this many half moves into the opening
+const MAX_PLY: usize = 40;
+
+/// An import finished in the background, waiting to be picked up by `receive_import`
+static IMPORT: Mutex<Option<Result<OpeningIndex, String>>> = Mutex::new(None);
+
+/// How often a move was played from a position and how those games ended
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MoveStats {
+    pub games: u32,
+    pub white_wins: u32,
+    pub draws: u32,
+    pub black_wins: u32,
+    rating_sum: u64,
+    rated_games: u32,
+}
+
+impl MoveStats {
+    fn add(&mut self, result: Option<&str>, rating: Option<u32>) {
+        self.games += 1;
+        match result {
+            Some("1-0") => self.white_wins += 1,
+            Some("0-1") => self.black_wins += 1,
+            Some("1/2-1/2") => self.draws += 1,
+            _ => {}
+        }
+        if let Some(rating) = rating {
+            self.rating_sum += rating as u64;
+            self.rated_games += 1;
+        }
+    }
+
+    /// The share of games won by White, drawn and won by Black, in percent
+    pub fn percentages(&self) -> [f32; 3] {
+        let finished = (self.white_wins + self.draws + self.black_wins).max(1) as f32;
+        [self.white_wins, self.draws, self.black_wins].map(|n| n as f32 * 100. / finished)
+    }
+
+    /// The average rating of both players over the games with known ratings
+    pub fn average_rating(&self) -> Option<u32> {
+        (self.rated_games > 0).then(|| (self.rating_sum / self.rated_games as u64) as u32)
+    }
+}
+
+#[derive(Default)]
+pub struct OpeningIndex {
+    positions: HashMap<u64, Vec<(ChessMove, MoveStats)>>,
+    pub games: usize,
+}
+
+impl OpeningIndex {
+    /// Indexes every standard game in the PGN file at `path`
+    pub fn import(path: &str) -> Result<OpeningIndex> {
+        let file = std::fs::File::open(path).with_context(|| format!("couldn't open {}", path))?;
+        let mut index = OpeningIndex::default();
+        for game in pgn::Reader::new(BufReader::new(file)) {
+            index.add(&game);
+        }
+        Ok(index)
+    }
+
+    /// Adds the opening moves of `game`, up to `MAX_PLY` half moves deep
+    fn add(&mut self, game: &PgnGame) {
+        if game.tag("Variant").is_some_and(|v| v != "Standard") {
+            return;
+        }
+        let mut board = match game.tag("FEN") {
+            Some(fen) => match fen.parse::<chess::Board>() {
+                Ok(board) => board,
+                Err(_) => return,
+            },
+            None => chess::Board::default(),
+        };
+        let result = game.tag("Result");
+        let elo = |name| game.tag(name).and_then(|r| r.parse::<u32>().ok());
+        let rating = match (elo("WhiteElo"), elo("BlackElo")) {
+            (Some(white), Some(black)) => Some((white + black) / 2),
+            (white, black) => white.or(black),
+        };
+
+        for san in game.moves.iter().take(MAX_PLY) {
+            let Some(m) = pgn::parse_san(&board, san) else {
+                break;
+            };
+            let moves = self.positions.entry(board.get_hash()).or_default();
+            match moves.iter_mut().find(|(played, _)| *played == m) {
+                Some((_, stats)) => stats.add(result, rating),
+                None => {
+                    let mut stats = MoveStats::default();
+                    stats.add(result, rating);
+                    moves.push((m, stats));
+                }
+            }
+            board = board.make_move_new(m);
+        }
+        self.games += 1;
+    }
+
+    /// The moves played from `board`, most popular first
+    pub fn moves(&self, board: &chess::Board) -> Vec<(ChessMove, MoveStats)> {
+        let mut moves = self
+            .positions
+            .get(&board.get_hash())
+            .cloned()
+            .unwrap_or_default();
+        moves.sort_by(|(_, a), (_, b)| b.games.cmp(&a.games));
+        moves
+    }
+}
+
+#[derive(Resource)]
+pub struct Explorer {
+    pub path: String,
+    pub index: OpeningIndex,
+    pub importing: bool,
+    pub error: Option<String>,
+}
+
+impl Default for Explorer {
+    fn default() -> Self {
+        Self {
+            path: String::from("games.pgn"),
+            index: OpeningIndex::default(),
+            importing: false,
+            error: None,
+        }
+    }
+}
+
+impl Explorer {
+    /// Imports the PGN file at `path` in the background, replacing the current index
+    /// once it's done
+    pub fn start_import(&mut self) {
+        self.importing = true;
+        self.error = None;
+        let path = self.path.clone();
+        let import = move || {
+            let index = OpeningIndex::import(&path).map_err(|e| format!("{e:#}"));
+            *IMPORT.lock().expect("Mutex should never be poisoned") = Some(index);
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(import);
+        #[cfg(target_arch = "wasm32")]
+        import();
+    }
+}
+
+pub fn receive_import(mut explorer: ResMut<Explorer>) {
+    if !explorer.importing {
+        return;
+    }
+    let Some(import) = IMPORT
+        .lock()
+        .expect("Mutex should never be poisoned")
+        .take()
+    else {
+        return;
+    };
+    explorer.importing = false;
+    match import {
+        Ok(index) => {
+            info!("Indexed {} games from {}", index.games, explorer.path);
+            explorer.index = index;
+        }
+        Err(e) => explorer.error = Some(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(pgn: &str) -> PgnGame {
+        pgn::Reader::new(pgn.as_bytes()).next().unwrap()
+    }
+
+    fn games_after(index: &OpeningIndex, moves: &[&str]) -> Vec<(String, u32)> {
+        let mut board = chess::Board::default();
+        for san in moves {
+            board = board.make_move_new(pgn::parse_san(&board, san).unwrap());
+        }
+        let mut counts: Vec<_> = index
+            .moves(&board)
+            .into_iter()
+            .map(|(m, stats)| (m.to_string(), stats.games))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn import_order_does_not_change_counts() {
+        let short = game("[Result \"1-0\"]\n\n1. e4 e5 1-0\n");
+        let long = game("[Result \"0-1\"]\n\n1. e4 e5 2. Nf3 Nc6 3. Bb5 0-1\n");
+
+        let mut short_first = OpeningIndex::default();
+        short_first.add(&short);
+        short_first.add(&long);
+        let mut long_first = OpeningIndex::default();
+        long_first.add(&long);
+        long_first.add(&short);
+
+        for moves in [&[][..], &["e4"], &["e4", "e5"], &["e4", "e5", "Nf3"]] {
+            assert_eq!(
+                games_after(&short_first, moves),
+                games_after(&long_first, moves)
+            );
+        }
+        assert_eq!(games_after(&short_first, &[]), [(String::from("e2e4"), 2)]);
+        assert_eq!(
+            games_after(&short_first, &["e4", "e5", "Nf3", "Nc6"]),
+            [(String::from("f1b5"), 1)]
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use chess::{Color, File, Square};
+
+use crate::variant::Position;
+
+/// Material the stronger side gives up before the game starts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Handicap {
+    #[default]
+    None,
+    /// The f-pawn, with the weaker side moving first
+    PawnAndMove,
+    /// The queen's knight
+    Knight,
+    /// The queen's rook
+    Rook,
+    Queen,
+}
+
+impl Handicap {
+    pub const ALL: [Handicap; 5] = [
+        Handicap::None,
+        Handicap::PawnAndMove,
+        Handicap::Knight,
+        Handicap::Rook,
+        Handicap::Queen,
+    ];
+
+    /// The name shown in the main menu and written to the PGN `Handicap` tag
+    pub fn name(self) -> &'static str {
+        match self {
+            Handicap::None => "None",
+            Handicap::PawnAndMove => "Pawn and move",
+            Handicap::Knight => "Knight odds",
+            Handicap::Rook => "Rook odds",
+            Handicap::Queen => "Queen odds",
+        }
+    }
+
+    /// Removes the handicapped piece of `giver` from a standard start position
+    pub fn apply(self, position: &mut Position, giver: Color) {
+        let back_rank = giver.to_my_backrank();
+        match self {
+            Handicap::None => return,
+            Handicap::PawnAndMove => {
+                position.clear_square(Square::make_square(giver.to_second_rank(), File::F));
+                position.set_side_to_move(!giver);
+            }
+            Handicap::Knight => position.clear_square(Square::make_square(back_rank, File::B)),
+            Handicap::Rook => position.clear_square(Square::make_square(back_rank, File::A)),
+            Handicap::Queen => position.clear_square(Square::make_square(back_rank, File::D)),
+        }
+        position.update_castle_rights();
+    }
+}
@@ -0,0 +1,95 @@
+use chess::{BitBoard, Color, Piece, ALL_PIECES, EMPTY};
+
+use crate::{
+    game::tree::{GameTree, NodeId},
+    variant::{crazyhouse, Position},
+};
+
+/// The light squares of the board, starting with b1
+const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA_55AA_55AA_55AA);
+
+/// The usual point value of a piece, with the king counting for nothing
+pub fn value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => 1,
+        Piece::Knight | Piece::Bishop => 3,
+        Piece::Rook => 5,
+        Piece::Queen => 9,
+        Piece::King => 0,
+    }
+}
+
+fn count(position: &Position, piece: Piece, color: Color) -> i32 {
+    (*position.pieces(piece) & *position.color_combined(color)).popcnt() as i32
+}
+
+/// The pieces of `color` taken off the board on the way to `id`, least valuable first
+///
+/// Each move is compared with the position before it, which tells a promoted
+/// pawn apart from a captured one.
+pub fn captured(tree: &GameTree, id: NodeId, color: Color) -> Vec<Piece> {
+    let mut captured = Vec::new();
+    for id in tree.path(id).into_iter().skip(1) {
+        let node = tree.node(id);
+        let (Some(parent), Some(m)) = (node.parent, node.mv) else {
+            continue;
+        };
+        let before = &tree.node(parent).board;
+        let promoted = before.side_to_move() == color
+            && m.get_promotion().is_some()
+            && crazyhouse::as_drop(m).is_none();
+        for piece in ALL_PIECES {
+            let mut lost = count(before, piece, color) - count(&node.board, piece, color);
+            if piece == Piece::Pawn && promoted {
+                lost -= 1;
+            }
+            for _ in 0..lost.max(0) {
+                captured.push(piece);
+            }
+        }
+    }
+    captured.sort_by_key(|piece| piece.to_index());
+    captured
+}
+
+/// White's material on the board minus Black's, in pawns
+pub fn balance(position: &Position) -> i32 {
+    ALL_PIECES
+        .iter()
+        .map(|&piece| {
+            (count(position, piece, Color::White) - count(position, piece, Color::Black))
+                * value(piece)
+        })
+        .sum()
+}
+
+/// The value of the pieces other than pawns left on the board, 62 in the start
+/// position and falling towards the endgame
+pub fn phase(position: &Position) -> i32 {
+    ALL_PIECES
+        .iter()
+        .filter(|&&piece| piece != Piece::Pawn)
+        .map(|&piece| {
+            (count(position, piece, Color::White) + count(position, piece, Color::Black))
+                * value(piece)
+        })
+        .sum()
+}
+
+/// True if neither side can ever checkmate, with nothing but kings and either a
+/// single knight or bishop or only bishops all on squares of one color
+pub fn insufficient(position: &Position) -> bool {
+    let mating = *position.pieces(Piece::Pawn)
+        | *position.pieces(Piece::Rook)
+        | *position.pieces(Piece::Queen);
+    if mating != EMPTY {
+        return false;
+    }
+    let knights = position.pieces(Piece::Knight).popcnt();
+    let bishops = *position.pieces(Piece::Bishop);
+    if knights + bishops.popcnt() <= 1 {
+        return true;
+    }
+    let light = bishops & LIGHT_SQUARES;
+    knights == 0 && (light == bishops || light == EMPTY)
+}
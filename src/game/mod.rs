@@ -0,0 +1,665 @@
+pub mod handicap;
+pub mod material;
+pub mod tree;
+
+use std::ops::Deref;
+
+use bevy::{ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
+use chess::{BitBoard, ChessMove, File, Piece, Rank, Square};
+
+use crate::{
+    audio::{PlaySound, Sound},
+    render::{pocket_at, DrawInfo},
+    variant::{self, chess960, crazyhouse, Position, Variant},
+    GameRule, GameState, Last50, SideToMove, TurnEndEvent,
+};
+use handicap::Handicap;
+use tree::{GameTree, NodeId};
+
+/// The game being played, dereferencing to the position at the current move
+#[derive(Resource, Default)]
+pub struct Board {
+    tree: GameTree,
+    /// The position the game is at while an earlier one is shown for review
+    live: Option<NodeId>,
+}
+
+impl Deref for Board {
+    type Target = Position;
+
+    fn deref(&self) -> &Self::Target {
+        self.tree.board()
+    }
+}
+
+impl Board {
+    pub fn tree(&self) -> &GameTree {
+        &self.tree
+    }
+
+    pub fn tree_mut(&mut self) -> &mut GameTree {
+        &mut self.tree
+    }
+
+    /// Plays a legal move from the current position
+    pub fn play(&mut self, m: ChessMove) {
+        self.tree.play(m);
+    }
+
+    /// Discards the game and starts a new one from `start`
+    pub fn reset(&mut self, start: chess::Board) {
+        self.tree = GameTree::new(start.into());
+        self.live = None;
+    }
+
+    /// Replaces the game with `tree`, continuing from its current node
+    pub fn load(&mut self, tree: GameTree) {
+        self.tree = tree;
+        self.live = None;
+    }
+
+    /// Discards the game and starts a new one as set up by `rule`
+    ///
+    /// The AI gives up the material of `handicap`.
+    pub fn new_game(&mut self, rule: &GameRule, handicap: Handicap) {
+        let (mut start, state) = rule.variant.start_position(rule.chess960_position);
+        handicap.apply(&mut start, rule.ai_color);
+        self.tree = GameTree::with_variant(rule.variant, start, state);
+        self.live = None;
+        if handicap != Handicap::None {
+            self.tree.set_tag("Handicap", handicap.name());
+        }
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.tree.variant()
+    }
+
+    /// The variant state of the current position
+    pub fn state(&self) -> &variant::State {
+        &self.tree.current_node().state
+    }
+
+    /// Starts the game over from its start position
+    pub fn restart(&mut self) {
+        self.tree.restart();
+        self.live = None;
+    }
+
+    /// True while an earlier position is shown, during which no moves can be made
+    pub fn is_reviewing(&self) -> bool {
+        self.live.is_some()
+    }
+
+    /// The position the game is at, even while reviewing
+    pub fn live(&self) -> NodeId {
+        self.live.unwrap_or(self.tree.current())
+    }
+
+    /// Shows the position at `id`, which must lead up to the live position,
+    /// without changing the game
+    pub fn review(&mut self, id: NodeId) {
+        let live = self.live();
+        self.tree.go_to(id);
+        self.live = (id != live).then_some(live);
+    }
+
+    /// Reviews the position before the one shown
+    pub fn review_back(&mut self) {
+        if let Some(parent) = self.tree.current_node().parent {
+            self.review(parent);
+        }
+    }
+
+    /// Reviews the position after the one shown, towards the live position
+    pub fn review_forward(&mut self) {
+        let path = self.tree.path(self.live());
+        let shown = path.iter().position(|&id| id == self.tree.current());
+        if let Some(&next) = shown.and_then(|i| path.get(i + 1)) {
+            self.review(next);
+        }
+    }
+
+    pub fn back_to_live(&mut self) {
+        if let Some(live) = self.live.take() {
+            self.tree.go_to(live);
+        }
+    }
+
+    /// Every move the rules of the variant allow in the current position
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        self.variant().rules().legal_moves(self, self.state())
+    }
+
+    pub fn is_legal(&self, m: ChessMove) -> bool {
+        self.legal_moves().contains(&m)
+    }
+
+    /// True if the side to move is in check, as far as the variant knows checks
+    pub fn in_check(&self) -> bool {
+        self.variant().rules().in_check(self, self.state())
+    }
+
+    /// How many times the current position has come up in the game, counting itself
+    pub fn repetitions(&self) -> usize {
+        let current = self.tree.current_node();
+        self.tree
+            .path(self.tree.current())
+            .into_iter()
+            .map(|id| self.tree.node(id))
+            .filter(|node| node.board == current.board && node.state == current.state)
+            .count()
+    }
+
+    /// True if neither side can checkmate anymore, which only ends the game in
+    /// variants won by checkmate alone
+    pub fn insufficient_material(&self) -> bool {
+        matches!(self.variant(), Variant::Standard | Variant::Chess960)
+            && material::insufficient(self)
+    }
+
+    /// A win reached through the variant's own rules in the current position
+    pub fn variant_outcome(&self) -> Option<crate::GameResult> {
+        self.variant().rules().outcome(self, self.state())
+    }
+
+    /// Interprets the king moving from `source` to `dest` as Chess960 castling
+    ///
+    /// Both the king moving onto the castling rook and the king moving straight to
+    /// its castled square are accepted, the latter only if it isn't a legal king
+    /// move on its own.
+    pub fn castle_move(&self, source: Square, dest: Square) -> Option<ChessMove> {
+        if self.variant() != Variant::Chess960 {
+            return None;
+        }
+        let board = self.chess();
+        let color = board.side_to_move();
+        chess960::castling_moves(&board, &self.state().castling)
+            .into_iter()
+            .find(|&m| {
+                m.get_source() == source
+                    && (m.get_dest() == dest
+                        || (chess960::king_destination(color, m) == dest
+                            && !board.legal(ChessMove::new(source, dest, None))))
+            })
+    }
+}
+
+/// The pieces of color `by` attacking `square`
+pub fn attackers(board: &chess::Board, square: Square, by: chess::Color) -> BitBoard {
+    Position::from(*board).attackers(square, by)
+}
+
+pub fn setup_game(mut board: ResMut<Board>, game_rule: Res<GameRule>) {
+    board.new_game(&game_rule, Handicap::None);
+}
+
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct PointedSquare(Option<chess::Square>);
+
+impl PointedSquare {
+    pub fn set(&mut self, square: Square) {
+        self.0 = Some(square);
+    }
+}
+
+#[derive(Resource, Clone, Copy)]
+pub enum SelectedPiece {
+    None,
+    Some {
+        piece: Piece,
+        square: Square,
+    },
+    /// A piece picked up from the side to move's Crazyhouse pocket
+    Pocket {
+        piece: Piece,
+    },
+    /// A pawn moved onto the last rank, waiting for the piece it promotes to
+    Promotion {
+        source: Square,
+        dest: Square,
+    },
+}
+
+/// The cursor position in world coordinates, if the cursor is inside the window
+pub fn cursor_world_position(
+    camera: &Query<(&Camera, &GlobalTransform)>,
+    window: &Query<&Window, With<PrimaryWindow>>,
+) -> Option<Vec2> {
+    let window = window.single();
+    let (camera, camera_transform) = camera.single();
+    window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .map(|ray| ray.origin.truncate())
+}
+
+pub fn mouse_point(
+    camera: Query<(&Camera, &GlobalTransform)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    view: crate::render::BoardView,
+    draw_info: Res<DrawInfo>,
+    mut pointed_square: ResMut<PointedSquare>,
+) {
+    let Some(pos) = cursor_world_position(&camera, &window) else {
+        return;
+    };
+
+    let square_size = draw_info.get_square_size();
+    let board_bound = square_size * 4.0;
+
+    if pos.x < -board_bound || pos.x > board_bound || pos.y < -board_bound || pos.y > board_bound {
+        pointed_square.0 = None;
+        return;
+    }
+
+    let mut cur_square = Vec2::new(
+        ((pos.x + (board_bound)) / square_size).ceil() - 1.,
+        ((pos.y + (board_bound)) / square_size).ceil() - 1.,
+    );
+
+    if view.flipped() {
+        cur_square.y = 7. - cur_square.y;
+    }
+
+    let rank = Rank::from_index(cur_square.y as usize);
+    let file = File::from_index(cur_square.x as usize);
+    let square = Square::make_square(rank, file);
+    pointed_square.set(square);
+}
+
+pub fn act(
+    camera: Query<(&Camera, &GlobalTransform)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    pointed_square: Res<PointedSquare>,
+    input: Res<ButtonInput<MouseButton>>,
+    draw_info: Res<DrawInfo>,
+    switch_sides: Res<crate::SwitchSides>,
+    flip_board: Res<crate::FlipBoard>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut board: ResMut<Board>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut move_writer: EventWriter<TurnEndEvent>,
+    mut sound_ev: EventWriter<PlaySound>,
+    mut last_50: ResMut<Last50>,
+    game_mode: Res<State<crate::GameMode>>,
+) {
+    // replays are only watched, even once they reach the end of the game
+    if board.is_reviewing() || **game_mode == crate::GameMode::Replay {
+        return;
+    }
+    // the board waits while the promotion piece is picked
+    if matches!(*selected_piece, SelectedPiece::Promotion { .. }) {
+        return;
+    }
+
+    if input.just_pressed(MouseButton::Left) && board.variant() == Variant::Crazyhouse {
+        let flipped = crate::render::is_flipped(side_to_move.0, &switch_sides, &flip_board);
+        let pocket = cursor_world_position(&camera, &window)
+            .and_then(|pos| pocket_at(&draw_info, flipped, pos));
+        if let Some((piece, color)) = pocket {
+            if color == board.side_to_move() && board.state().in_pocket(color, piece) > 0 {
+                *selected_piece = SelectedPiece::Pocket { piece };
+            }
+            return;
+        }
+    }
+
+    // pocket pieces are dragged onto the board and dropped where the button is released
+    if input.just_released(MouseButton::Left) {
+        if let (SelectedPiece::Pocket { piece }, Some(square)) = (*selected_piece, **pointed_square)
+        {
+            let played = drop_piece(
+                piece,
+                square,
+                board.as_mut(),
+                selected_piece.as_mut(),
+                side_to_move.as_mut(),
+                last_50.as_mut(),
+            );
+            if !played {
+                sound_ev.send(PlaySound(Sound::Illegal));
+            }
+            move_writer.send(TurnEndEvent);
+        }
+    }
+
+    if input.just_pressed(MouseButton::Left) && pointed_square.is_some() {
+        let square = pointed_square.unwrap();
+        let target_col = board.color_on(square);
+
+        match (*selected_piece, target_col) {
+            (SelectedPiece::None, None) => return,
+            (SelectedPiece::None, Some(col)) => {
+                try_select(col, square, board.as_mut(), selected_piece.as_mut());
+            }
+            (SelectedPiece::Some { square: source, .. }, None) => {
+                let attempt = make_move(
+                    source,
+                    square,
+                    None,
+                    board.as_mut(),
+                    selected_piece.as_mut(),
+                    side_to_move.as_mut(),
+                    last_50.as_mut(),
+                );
+                if attempt == Attempt::Illegal {
+                    sound_ev.send(PlaySound(Sound::Illegal));
+                }
+            }
+            (SelectedPiece::Some { square: source, .. }, Some(col)) => {
+                let castles = board.castle_move(source, square).is_some();
+                if !castles && try_select(col, square, board.as_mut(), selected_piece.as_mut()) {
+                } else {
+                    let attempt = make_move(
+                        source,
+                        square,
+                        None,
+                        board.as_mut(),
+                        selected_piece.as_mut(),
+                        side_to_move.as_mut(),
+                        last_50.as_mut(),
+                    );
+                    if attempt == Attempt::Illegal {
+                        sound_ev.send(PlaySound(Sound::Illegal));
+                    }
+                }
+            }
+            (SelectedPiece::Pocket { piece }, None) => {
+                let played = drop_piece(
+                    piece,
+                    square,
+                    board.as_mut(),
+                    selected_piece.as_mut(),
+                    side_to_move.as_mut(),
+                    last_50.as_mut(),
+                );
+                if !played {
+                    sound_ev.send(PlaySound(Sound::Illegal));
+                }
+            }
+            (SelectedPiece::Pocket { .. }, Some(col)) => {
+                if !try_select(col, square, board.as_mut(), selected_piece.as_mut()) {
+                    *selected_piece = SelectedPiece::None;
+                }
+            }
+            (SelectedPiece::Promotion { .. }, _) => {}
+        }
+        move_writer.send(TurnEndEvent);
+    }
+}
+
+/// The pieces a pawn moving from `source` to `dest` may promote to, most valuable first
+pub fn promotions(board: &Board, source: Square, dest: Square) -> Vec<Piece> {
+    let legal = board.legal_moves();
+    [
+        Piece::Queen,
+        Piece::Rook,
+        Piece::Bishop,
+        Piece::Knight,
+        Piece::King,
+    ]
+    .into_iter()
+    .filter(|&piece| legal.contains(&ChessMove::new(source, dest, Some(piece))))
+    .collect()
+}
+
+/// Finishes the promotion waiting in `selected_piece` with `piece`, returning
+/// whether it was played
+pub fn promote(
+    piece: Piece,
+    board: &mut Board,
+    selected_piece: &mut SelectedPiece,
+    side_to_move: &mut SideToMove,
+    last_50: &mut Last50,
+) -> bool {
+    let SelectedPiece::Promotion { source, dest } = *selected_piece else {
+        return false;
+    };
+    let attempt = make_move(
+        source,
+        dest,
+        Some(piece),
+        board,
+        selected_piece,
+        side_to_move,
+        last_50,
+    );
+    attempt == Attempt::Played
+}
+
+/// What became of a move tried on the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Attempt {
+    Played,
+    Illegal,
+    /// A pawn reached the last rank and waits in `selected_piece` for [`promote`]
+    Promoting,
+}
+
+/// Plays the move from `source` to `dest` if it's legal
+///
+/// A pawn reaching the last rank without `promotion` isn't played yet, but left
+/// waiting in `selected_piece` for [`promote`].
+fn make_move(
+    source: Square,
+    dest: Square,
+    promotion: Option<Piece>,
+    board: &mut Board,
+    selected_piece: &mut SelectedPiece,
+    side_to_move: &mut SideToMove,
+    last_50: &mut Last50,
+) -> Attempt {
+    let m = board
+        .castle_move(source, dest)
+        .unwrap_or(ChessMove::new(source, dest, promotion));
+    *selected_piece = SelectedPiece::None;
+    if !board.is_legal(m) {
+        if promotion.is_none() && !promotions(board, source, dest).is_empty() {
+            *selected_piece = SelectedPiece::Promotion { source, dest };
+            return Attempt::Promoting;
+        }
+        return Attempt::Illegal;
+    }
+    last_50.push(board.resets_fifty_moves(m));
+    board.play(m);
+    side_to_move.0 = board.side_to_move();
+    Attempt::Played
+}
+
+fn drop_piece(
+    piece: Piece,
+    square: Square,
+    board: &mut Board,
+    selected_piece: &mut SelectedPiece,
+    side_to_move: &mut SideToMove,
+    last_50: &mut Last50,
+) -> bool {
+    let m = crazyhouse::drop_move(piece, square);
+    *selected_piece = SelectedPiece::None;
+    if !board.is_legal(m) {
+        return false;
+    }
+    board.play(m);
+    side_to_move.0 = board.side_to_move();
+    last_50.push(false);
+    true
+}
+
+fn try_select(
+    target_color: chess::Color,
+    square: Square,
+    board: &mut Board,
+    selected_piece: &mut SelectedPiece,
+) -> bool {
+    if target_color == board.side_to_move() {
+        *selected_piece = SelectedPiece::Some {
+            square,
+            piece: board.piece_on(square).unwrap(),
+        };
+        true
+    } else {
+        false
+    }
+}
+
+pub fn check_end(
+    mut up_ev: EventReader<TurnEndEvent>,
+    mut board: ResMut<Board>,
+    mut state: ResMut<NextState<GameState>>,
+    mut result: ResMut<crate::GameResult>,
+    last_50: Res<Last50>,
+) {
+    for _ in up_ev.read() {
+        // the variant's rules decide which moves are left, so `board.status()` can't be used
+        let outcome = if let Some(outcome) = board.variant_outcome() {
+            info!("{} win: {:?}", board.variant().name(), outcome);
+            outcome
+        } else if board.legal_moves().is_empty() {
+            if board.in_check() {
+                let color = !board.side_to_move();
+                info!("Checkmate! Winner: {:?}", color);
+                crate::GameResult::Checkmate { winner: color }
+            } else {
+                info!("Stalemate");
+                crate::GameResult::Stalemate
+            }
+        } else if last_50.should_draw() {
+            info!("Draw by the fifty move rule");
+            crate::GameResult::FiftyMoves
+        } else if board.repetitions() >= 3 {
+            info!("Draw by repetition");
+            crate::GameResult::Repetition
+        } else if board.insufficient_material() {
+            info!("Draw by insufficient material");
+            crate::GameResult::InsufficientMaterial
+        } else {
+            continue;
+        };
+        state.set(GameState::End);
+        outcome.record(board.tree_mut());
+        *result = outcome;
+        return;
+    }
+}
+
+/// A draw offered during the game, which stands until the next move
+#[derive(Resource, Default)]
+pub struct DrawOffer {
+    /// The side offering the draw and the position it was offered in
+    pub offered: Option<(chess::Color, NodeId)>,
+    /// The position in which the last offer was declined
+    pub declined: Option<NodeId>,
+}
+
+/// Withdraws any draw offer once its game is over or left
+pub fn clear_draw_offer(mut draw_offer: ResMut<DrawOffer>) {
+    *draw_offer = DrawOffer::default();
+}
+
+/// Ends the game other than by a move, such as by a resignation
+#[derive(SystemParam)]
+pub struct Ending<'w> {
+    state: ResMut<'w, NextState<GameState>>,
+    result: ResMut<'w, crate::GameResult>,
+    pub draw_offer: ResMut<'w, DrawOffer>,
+}
+
+impl Ending<'_> {
+    pub fn finish(&mut self, board: &mut Board, outcome: crate::GameResult) {
+        board.back_to_live();
+        outcome.record(board.tree_mut());
+        *self.result = outcome;
+        self.state.set(GameState::End);
+    }
+}
+
+/// Steps through the moves with the arrow keys, reviewing them outside of analysis
+pub fn step_through_moves(
+    mut contexts: bevy_egui::EguiContexts,
+    input: Res<ButtonInput<KeyCode>>,
+    game_mode: Res<State<crate::GameMode>>,
+    mut board: ResMut<Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let back = input.just_pressed(KeyCode::ArrowLeft);
+    let forward = input.just_pressed(KeyCode::ArrowRight);
+    if !back && !forward {
+        return;
+    }
+
+    let before = board.tree().current();
+    match (**game_mode == crate::GameMode::Analysis, back) {
+        (true, true) => {
+            board.tree_mut().back();
+        }
+        (true, false) => {
+            board.tree_mut().forward();
+        }
+        (false, true) => board.review_back(),
+        (false, false) => board.review_forward(),
+    }
+    if board.tree().current() != before {
+        if **game_mode == crate::GameMode::Analysis {
+            side_to_move.0 = board.side_to_move();
+        }
+        *selected_piece = SelectedPiece::None;
+        up_ev.send(TurnEndEvent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn promotions_wait_for_a_piece() {
+        let mut board = Board::default();
+        board.reset(chess::Board::from_str("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap());
+        let mut selected_piece = SelectedPiece::None;
+        let mut side_to_move = SideToMove(chess::Color::White);
+        let mut last_50 = Last50::default();
+
+        let attempt = make_move(
+            Square::A7,
+            Square::B8,
+            None,
+            &mut board,
+            &mut selected_piece,
+            &mut side_to_move,
+            &mut last_50,
+        );
+        assert_eq!(attempt, Attempt::Illegal);
+
+        let attempt = make_move(
+            Square::A7,
+            Square::A8,
+            None,
+            &mut board,
+            &mut selected_piece,
+            &mut side_to_move,
+            &mut last_50,
+        );
+        assert_eq!(attempt, Attempt::Promoting);
+        assert_eq!(board.tree().current(), GameTree::ROOT);
+        assert_eq!(side_to_move.0, chess::Color::White);
+
+        assert!(promote(
+            Piece::Knight,
+            &mut board,
+            &mut selected_piece,
+            &mut side_to_move,
+            &mut last_50,
+        ));
+        assert_eq!(board.piece_on(Square::A8), Some(Piece::Knight));
+        assert_eq!(side_to_move.0, chess::Color::Black);
+        assert!(matches!(selected_piece, SelectedPiece::None));
+    }
+}
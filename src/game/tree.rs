@@ -0,0 +1,512 @@
+use chess::ChessMove;
+
+use crate::{
+    annotation::Shape,
+    variant::{chess960, Position, State, Variant},
+};
+
+pub type NodeId = usize;
+
+/// Tags filled in when a game ends, which don't carry over to a restart
+const END_OF_GAME_TAGS: [&str; 5] = ["Result", "Termination", "White", "Black", "Date"];
+
+/// A single position in the game tree along with the move that led to it
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub board: Position,
+    /// `None` only for the root node
+    pub mv: Option<ChessMove>,
+    /// The move in standard algebraic notation, empty for the root node
+    pub san: String,
+    pub parent: Option<NodeId>,
+    /// The first child is the main continuation, the rest are variations
+    pub children: Vec<NodeId>,
+    pub comment: String,
+    /// Numeric annotation glyphs, as written after a `$` in PGN
+    pub nags: Vec<u8>,
+    /// Arrows and circles drawn on the position
+    pub shapes: Vec<Shape>,
+    /// Variant specific parts of the position
+    pub state: State,
+}
+
+impl Node {
+    fn new(board: Position, mv: Option<ChessMove>, san: String, parent: Option<NodeId>) -> Self {
+        Self {
+            board,
+            mv,
+            san,
+            parent,
+            children: Vec::new(),
+            comment: String::new(),
+            nags: Vec::new(),
+            shapes: Vec::new(),
+            state: State::default(),
+        }
+    }
+}
+
+/// The main line of a game together with every variation branching off of it
+///
+/// Nodes are stored in an arena and never removed from it; deleting a variation
+/// only detaches it from its parent.
+#[derive(Debug, Clone)]
+pub struct GameTree {
+    nodes: Vec<Node>,
+    current: NodeId,
+    tags: Vec<(String, String)>,
+    variant: Variant,
+    /// Half moves without a capture or pawn move before the start position
+    halfmove_clock: usize,
+    /// The move number of the start position
+    fullmove_number: usize,
+}
+
+impl Default for GameTree {
+    fn default() -> Self {
+        Self::new(Position::default())
+    }
+}
+
+impl GameTree {
+    pub const ROOT: NodeId = 0;
+
+    pub fn new(start: Position) -> Self {
+        Self {
+            nodes: vec![Node::new(start, None, String::new(), None)],
+            current: Self::ROOT,
+            tags: Vec::new(),
+            variant: Variant::Standard,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    /// Starts a game of `variant` from `start`
+    pub fn with_variant(variant: Variant, start: Position, state: State) -> Self {
+        let mut tree = Self::new(start);
+        tree.nodes[Self::ROOT].state = state;
+        tree.variant = variant;
+        if variant != Variant::Standard {
+            tree.set_tag("Variant", variant.name());
+        }
+        tree
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Sets the halfmove clock of the start position, as read from its FEN
+    pub fn set_halfmove_clock(&mut self, halfmoves: usize) {
+        self.halfmove_clock = halfmoves;
+    }
+
+    /// Sets the move number of the start position, as read from its FEN
+    pub fn set_fullmove_number(&mut self, number: usize) {
+        self.fullmove_number = number;
+    }
+
+    /// The move number at `id`, which goes up after every move by Black
+    pub fn fullmove_number(&self, id: NodeId) -> usize {
+        let black_started = self.start_board().side_to_move() == chess::Color::Black;
+        self.fullmove_number + (self.ply(id) + black_started as usize) / 2
+    }
+
+    /// Half moves without a capture or pawn move up to `id`, counting those before
+    /// the start position
+    pub fn halfmove_clock(&self, id: NodeId) -> usize {
+        self.path(id)
+            .into_iter()
+            .skip(1)
+            .fold(self.halfmove_clock, |halfmoves, id| {
+                let node = &self.nodes[id];
+                match (node.parent, node.mv) {
+                    (Some(parent), Some(m)) if self.nodes[parent].board.resets_fifty_moves(m) => 0,
+                    _ => halfmoves + 1,
+                }
+            })
+    }
+
+    /// Discards every move, keeping the start position and the tags set before
+    /// the game was played
+    pub fn restart(&mut self) {
+        self.nodes.truncate(1);
+        self.nodes[Self::ROOT].children.clear();
+        self.current = Self::ROOT;
+        self.tags
+            .retain(|(name, _)| !END_OF_GAME_TAGS.contains(&name.as_str()));
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn current(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn current_node(&self) -> &Node {
+        &self.nodes[self.current]
+    }
+
+    pub fn current_node_mut(&mut self) -> &mut Node {
+        &mut self.nodes[self.current]
+    }
+
+    /// The position at the current node
+    pub fn board(&self) -> &Position {
+        &self.nodes[self.current].board
+    }
+
+    pub fn start_board(&self) -> &Position {
+        &self.nodes[Self::ROOT].board
+    }
+
+    /// Plays `m` from the current node and moves to the resulting node
+    ///
+    /// If the move was already played from here, the existing node is reused.
+    /// Otherwise a new child is created, which becomes the main continuation
+    /// when the current node has none yet.
+    /// The move is assumed to be legal in the current position.
+    pub fn play(&mut self, m: ChessMove) -> NodeId {
+        let existing = self.nodes[self.current]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].mv == Some(m));
+        if let Some(child) = existing {
+            self.current = child;
+            return child;
+        }
+
+        let parent = &self.nodes[self.current];
+        let rules = self.variant.rules();
+        let legal = rules.legal_moves(&parent.board, &parent.state);
+        let (board, state) = rules.play(&parent.board, &parent.state, m);
+        let check = rules.in_check(&board, &state);
+        let checkmate = check && rules.legal_moves(&board, &state).is_empty();
+        let san = crate::pgn::san(&parent.board, m, &legal, check, checkmate);
+        let id = self.nodes.len();
+        let mut node = Node::new(board, Some(m), san, Some(self.current));
+        node.state = state;
+        self.nodes.push(node);
+        self.nodes[self.current].children.push(id);
+        self.current = id;
+        id
+    }
+
+    pub fn go_to(&mut self, id: NodeId) {
+        self.current = id;
+    }
+
+    /// Steps back one move, returning false when already at the start
+    pub fn back(&mut self) -> bool {
+        match self.nodes[self.current].parent {
+            Some(parent) => {
+                self.current = parent;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Follows the main continuation one move, returning false at the end of the line
+    pub fn forward(&mut self) -> bool {
+        match self.nodes[self.current].children.first() {
+            Some(&child) => {
+                self.current = child;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn to_start(&mut self) {
+        self.current = Self::ROOT;
+    }
+
+    /// Follows the main continuation of the current line to its last move
+    pub fn to_end(&mut self) {
+        while self.forward() {}
+    }
+
+    /// The last move of the main line
+    pub fn main_line_end(&self) -> NodeId {
+        let mut node = Self::ROOT;
+        while let Some(&child) = self.nodes[node].children.first() {
+            node = child;
+        }
+        node
+    }
+
+    /// True if `ancestor` lies on the path from the root to `id`, inclusive
+    pub fn is_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut node = Some(id);
+        while let Some(n) = node {
+            if n == ancestor {
+                return true;
+            }
+            node = self.nodes[n].parent;
+        }
+        false
+    }
+
+    /// True if `id` is reached from the root only through main continuations
+    pub fn is_main_line(&self, id: NodeId) -> bool {
+        let mut node = id;
+        while let Some(parent) = self.nodes[node].parent {
+            if self.nodes[parent].children.first() != Some(&node) {
+                return false;
+            }
+            node = parent;
+        }
+        true
+    }
+
+    /// Makes the variation containing `id` the main continuation at its branching point
+    ///
+    /// Returns false if `id` is already part of the main line.
+    pub fn promote(&mut self, id: NodeId) -> bool {
+        let mut node = id;
+        while let Some(parent) = self.nodes[node].parent {
+            let siblings = &mut self.nodes[parent].children;
+            let index = siblings.iter().position(|&c| c == node).unwrap();
+            if index != 0 {
+                let branch = siblings.remove(index);
+                siblings.insert(0, branch);
+                return true;
+            }
+            node = parent;
+        }
+        false
+    }
+
+    /// Removes the move at `id` and everything after it
+    ///
+    /// If the current node was inside the removed subtree, the current node
+    /// becomes the parent of `id`. The root cannot be deleted.
+    pub fn delete(&mut self, id: NodeId) -> bool {
+        let Some(parent) = self.nodes[id].parent else {
+            return false;
+        };
+        if self.is_ancestor(id, self.current) {
+            self.current = parent;
+        }
+        self.nodes[parent].children.retain(|&c| c != id);
+        true
+    }
+
+    /// The moves leading from the root to the current node
+    pub fn moves(&self) -> Vec<ChessMove> {
+        self.path(self.current)
+            .into_iter()
+            .filter_map(|id| self.nodes[id].mv)
+            .collect()
+    }
+
+    /// The nodes from the root to `id`, inclusive
+    pub fn path(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![id];
+        let mut node = id;
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(parent);
+            node = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Number of half moves from the root to `id`
+    pub fn ply(&self, id: NodeId) -> usize {
+        self.path(id).len() - 1
+    }
+
+    /// The FEN of the position at `id`, including the variant's own fields
+    ///
+    /// Chess960 castling rights are written as X-FEN, or as Shredder-FEN if `shredder` is set.
+    pub fn fen(&self, id: NodeId, shredder: bool) -> String {
+        let node = &self.nodes[id];
+        self.with_counters(id, self.variant.fen(&node.board, &node.state, shredder))
+    }
+
+    /// The FEN of the position at `id` as standard chess engines read it, leaving out
+    /// Crazyhouse pockets and Three-check counts
+    pub fn engine_fen(&self, id: NodeId) -> String {
+        let node = &self.nodes[id];
+        let fen = match self.variant {
+            Variant::Chess960 => chess960::fen(&node.board.chess(), &node.state.castling, true),
+            _ => node.board.to_string(),
+        };
+        self.with_counters(id, fen)
+    }
+
+    /// Fills in the move counters of a FEN for the position at `id`
+    fn with_counters(&self, id: NodeId, fen: String) -> String {
+        // positions don't keep the counters, which follow from the moves
+        let mut fields: Vec<String> = fen.split(' ').map(String::from).collect();
+        if let Some(clock) = fields.get_mut(4) {
+            *clock = self.halfmove_clock(id).to_string();
+        }
+        if let Some(number) = fields.get_mut(5) {
+            *number = self.fullmove_number(id).to_string();
+        }
+        fields.join(" ")
+    }
+
+    /// Toggles a numeric annotation glyph on the current node
+    pub fn toggle_nag(&mut self, nag: u8) {
+        let nags = &mut self.nodes[self.current].nags;
+        if let Some(index) = nags.iter().position(|&n| n == nag) {
+            nags.remove(index);
+        } else {
+            nags.push(nag);
+        }
+    }
+
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets a PGN tag, replacing any previous value
+    pub fn set_tag(&mut self, name: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.tags.push((name.to_string(), value)),
+        }
+    }
+}
+
+/// The halfmove clock field of `fen`, 0 if it has none
+pub fn fen_halfmove_clock(fen: &str) -> usize {
+    // Three-check counts may come before the move counters
+    fen.split_whitespace()
+        .filter(|field| !field.contains('+'))
+        .nth(4)
+        .and_then(|clock| clock.parse().ok())
+        .unwrap_or(0)
+}
+
+/// The fullmove number field of `fen`, 1 if it has none
+pub fn fen_fullmove_number(fen: &str) -> usize {
+    fen.split_whitespace()
+        .filter(|field| !field.contains('+'))
+        .nth(5)
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::parse_uci;
+
+    fn play(tree: &mut GameTree, moves: &[&str]) -> NodeId {
+        for uci in moves {
+            tree.play(parse_uci(uci).unwrap());
+        }
+        tree.current()
+    }
+
+    #[test]
+    fn play_reuses_moves_already_played() {
+        let mut tree = GameTree::default();
+        let e4 = play(&mut tree, &["e2e4"]);
+        tree.to_start();
+        assert_eq!(play(&mut tree, &["e2e4"]), e4);
+        assert_eq!(tree.node(GameTree::ROOT).children, [e4]);
+        assert_eq!(tree.current_node().san, "e4");
+    }
+
+    #[test]
+    fn variations_branch_off_the_main_line() {
+        let mut tree = GameTree::default();
+        let e5 = play(&mut tree, &["e2e4", "e7e5"]);
+        tree.back();
+        let c5 = play(&mut tree, &["c7c5"]);
+        let e4 = tree.node(c5).parent.unwrap();
+        assert_eq!(tree.node(e4).children, [e5, c5]);
+        assert!(tree.is_main_line(e5));
+        assert!(!tree.is_main_line(c5));
+        assert_eq!(tree.path(c5), [GameTree::ROOT, e4, c5]);
+        assert_eq!(tree.ply(c5), 2);
+
+        tree.to_start();
+        tree.to_end();
+        assert_eq!(tree.current(), e5);
+        assert_eq!(tree.main_line_end(), e5);
+    }
+
+    #[test]
+    fn promote_makes_a_variation_the_main_line() {
+        let mut tree = GameTree::default();
+        let e5 = play(&mut tree, &["e2e4", "e7e5"]);
+        tree.back();
+        let nf3 = play(&mut tree, &["c7c5", "g1f3"]);
+        assert!(tree.promote(nf3));
+        assert!(tree.is_main_line(nf3));
+        assert!(!tree.is_main_line(e5));
+        assert_eq!(tree.main_line_end(), nf3);
+        assert!(!tree.promote(nf3));
+    }
+
+    #[test]
+    fn delete_removes_everything_after_a_move() {
+        let mut tree = GameTree::default();
+        let e4 = play(&mut tree, &["e2e4"]);
+        let e5 = play(&mut tree, &["e7e5"]);
+        play(&mut tree, &["g1f3"]);
+        assert!(tree.delete(e5));
+        assert_eq!(tree.current(), e4);
+        assert!(tree.node(e4).children.is_empty());
+        assert!(!tree.delete(GameTree::ROOT));
+    }
+
+    #[test]
+    fn fen_counts_moves() {
+        let mut tree = GameTree::default();
+        let nf3 = play(&mut tree, &["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(
+            tree.fen(nf3, false),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+        let nc6 = play(&mut tree, &["b8c6"]);
+        assert!(tree.fen(nc6, false).ends_with(" 2 3"));
+    }
+
+    #[test]
+    fn fen_continues_the_counters_of_the_start_position() {
+        let fen = "4k3/8/8/8/8/8/8/R3K3 b - - 10 40";
+        let mut tree = GameTree::new(fen.parse().unwrap());
+        tree.set_halfmove_clock(fen_halfmove_clock(fen));
+        tree.set_fullmove_number(fen_fullmove_number(fen));
+        assert_eq!(tree.fen(GameTree::ROOT, false), fen);
+
+        let kd8 = play(&mut tree, &["e8d8"]);
+        assert!(tree.fen(kd8, false).ends_with(" w - - 11 41"));
+        let ra8 = play(&mut tree, &["a1a8"]);
+        assert!(tree.fen(ra8, false).ends_with(" b - - 12 41"));
+    }
+
+    #[test]
+    fn restart_keeps_tags_set_before_the_game() {
+        let mut tree = GameTree::default();
+        tree.set_tag("Event", "Casual");
+        play(&mut tree, &["e2e4"]);
+        tree.set_tag("Result", "1-0");
+        tree.restart();
+        assert_eq!(tree.current(), GameTree::ROOT);
+        assert!(tree.node(GameTree::ROOT).children.is_empty());
+        assert_eq!(tree.tag("Event"), Some("Casual"));
+        assert_eq!(tree.tag("Result"), None);
+    }
+}
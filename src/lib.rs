@@ -2,9 +2,23 @@
 #![allow(clippy::type_complexity)]
 
 pub mod ai;
+pub mod annotation;
+pub mod audio;
+pub mod editor;
+pub mod explorer;
 pub mod game;
+pub mod library;
+pub mod openings;
+pub mod pgn;
+pub mod puzzle;
 pub mod render;
+pub mod replay;
+pub mod saves;
+pub mod settings;
+pub mod storage;
+pub mod theme;
 pub mod ui;
+pub mod variant;
 
 use bevy::prelude::{Component, Event, Resource, States};
 use futures::Future;
@@ -12,53 +26,78 @@ use futures::Future;
 #[derive(Event, Debug)]
 pub struct TurnEndEvent;
 
-#[derive(Resource, Debug)]
+/// Half moves played since the last capture or pawn move, for the fifty move rule
+#[derive(Resource, Default, Debug)]
 pub struct Last50 {
-    counter: usize,
-    data: [bool; 50],
+    halfmoves: usize,
 }
 
-impl Default for Last50 {
-    fn default() -> Self {
+impl Last50 {
+    /// The count at the live position of the game on `board`
+    pub fn following(board: &game::Board) -> Self {
         Self {
-            counter: 0,
-            data: [true; 50],
+            halfmoves: board.tree().halfmove_clock(board.live()),
         }
     }
-}
 
-impl Last50 {
-    pub fn push(&mut self, data: bool) {
-        self.data[self.counter] = data;
-        self.counter += 1;
-        if self.counter >= 50 {
-            self.counter = 0;
-        }
+    /// Counts a half move, which resets the count if it's a capture or pawn move
+    pub fn push(&mut self, resets: bool) {
+        self.halfmoves = if resets { 0 } else { self.halfmoves + 1 };
     }
 
-    /// Returns true if the game should end in a draw
+    /// Returns true once each side made fifty moves without a capture or pawn move
     pub fn should_draw(&self) -> bool {
-        !self.data.into_iter().reduce(|a, b| a | b).unwrap_or(false)
+        self.halfmoves >= 100
     }
 }
 
+/// The sprite of a piece standing on `square`
 #[derive(Component)]
-pub struct Piece;
+pub struct Piece {
+    pub square: chess::Square,
+    pub piece: chess::Piece,
+    pub color: chess::Color,
+}
 
+/// The sprite of a board square
 #[derive(Component)]
-pub struct Square;
+pub struct Square(pub chess::Square);
 
 #[derive(Component)]
 pub struct Selector;
 
+/// A last move or check highlight under the pieces, hidden while not needed
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum Highlight {
+    LastMoveFrom,
+    LastMoveTo,
+    Check,
+}
+
+/// A Crazyhouse pocket panel or a piece held in it
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum Pocket {
+    Panel(chess::Color),
+    /// The `index`th copy of `piece` in the pocket of `color`
+    Piece {
+        piece: chess::Piece,
+        color: chess::Color,
+        index: u8,
+    },
+}
+
 #[derive(Default, States, Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
     #[default]
     MainMenu,
+    Editor,
     Playing,
     End,
+    /// Browsing the game library
+    Library,
 }
 
+/// How a game ended, or that it hasn't yet
 #[derive(Resource, Default, Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameResult {
     #[default]
@@ -67,23 +106,121 @@ pub enum GameResult {
         winner: chess::Color,
     },
     Stalemate,
+    /// A win through a variant's own rules, such as the third check in Three-check
+    VariantWin {
+        winner: chess::Color,
+    },
+    /// Fifty moves by each side without a capture or pawn move
+    FiftyMoves,
+    /// The same position came up for the third time
+    Repetition,
+    /// Neither side has the pieces left to checkmate
+    InsufficientMaterial,
+    /// The loser ran out of time
+    Timeout {
+        winner: chess::Color,
+    },
+    /// The loser resigned
+    Resignation {
+        winner: chess::Color,
+    },
+    /// The players agreed to a draw
+    DrawAgreed,
+}
+
+impl GameResult {
+    /// The side that won, or `None` for a draw or an unfinished game
+    pub fn winner(&self) -> Option<chess::Color> {
+        match *self {
+            GameResult::Checkmate { winner }
+            | GameResult::VariantWin { winner }
+            | GameResult::Timeout { winner }
+            | GameResult::Resignation { winner } => Some(winner),
+            GameResult::Ongoing
+            | GameResult::Stalemate
+            | GameResult::FiftyMoves
+            | GameResult::Repetition
+            | GameResult::InsufficientMaterial
+            | GameResult::DrawAgreed => None,
+        }
+    }
+
+    /// The result as written in the PGN `Result` tag
+    pub fn pgn_result(&self) -> &'static str {
+        match (self, self.winner()) {
+            (GameResult::Ongoing, _) => "*",
+            (_, Some(chess::Color::White)) => "1-0",
+            (_, Some(chess::Color::Black)) => "0-1",
+            (_, None) => "1/2-1/2",
+        }
+    }
+
+    /// How the game ended, as shown on the end screen
+    pub fn description(&self) -> String {
+        match self {
+            GameResult::Ongoing => String::from("The game is still going"),
+            GameResult::Checkmate { winner } => format!("{:?} won by checkmate", winner),
+            GameResult::Stalemate => String::from("Game drawn by stalemate"),
+            GameResult::VariantWin { winner } => format!("{:?} won", winner),
+            GameResult::FiftyMoves => String::from("Game drawn by the fifty move rule"),
+            GameResult::Repetition => String::from("Game drawn by threefold repetition"),
+            GameResult::InsufficientMaterial => String::from("Game drawn by insufficient material"),
+            GameResult::Timeout { winner } => format!("{:?} won on time", winner),
+            GameResult::Resignation { winner } => format!("{:?} won by resignation", winner),
+            GameResult::DrawAgreed => String::from("Game drawn by agreement"),
+        }
+    }
+
+    /// The PGN `Termination` tag, which only tells a game ended by the rules
+    /// apart from one lost on time
+    pub fn termination(&self) -> &'static str {
+        match self {
+            GameResult::Ongoing => "unterminated",
+            GameResult::Timeout { .. } => "time forfeit",
+            _ => "normal",
+        }
+    }
+
+    /// Writes the result and how it came about to the tags of `tree`
+    pub fn record(&self, tree: &mut game::tree::GameTree) {
+        tree.set_tag("Result", self.pgn_result());
+        tree.set_tag("Termination", self.termination());
+    }
 }
 
 #[derive(Resource)]
 pub struct SideToMove(pub chess::Color);
 
+/// Turns the board towards the side to move after every move
 #[derive(Resource)]
 pub struct SwitchSides(pub bool);
 
+/// Turns the board around, independently of `SwitchSides`
+#[derive(Resource, Default)]
+pub struct FlipBoard(pub bool);
+
 #[derive(Resource)]
 pub struct GameRule {
+    /// Whether hotseat games start with `SwitchSides` on
+    auto_rotate: bool,
     ai_color: chess::Color,
+    engine: ai::Engine,
+    variant: variant::Variant,
+    /// The Chess960 start position, numbered 0 to 959
+    chess960_position: u16,
+    /// Material the AI gives up in standard games against it
+    handicap: game::handicap::Handicap,
 }
 
 impl Default for GameRule {
     fn default() -> Self {
         Self {
+            auto_rotate: true,
             ai_color: chess::Color::Black,
+            engine: ai::Engine::StockfishOnline,
+            variant: variant::Variant::Standard,
+            chess960_position: variant::chess960::STANDARD_POSITION,
+            handicap: game::handicap::Handicap::None,
         }
     }
 }
@@ -94,6 +231,11 @@ pub enum GameMode {
     Hotseat,
     VsAi,
     Sim,
+    Analysis,
+    /// Solving tactics puzzles, with the opponent's replies played automatically
+    Puzzle,
+    /// Going through a game opened from the library
+    Replay,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
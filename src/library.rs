@@ -0,0 +1,573 @@
+//! The game library: finished, saved and imported games kept on disk
+//!
+//! Games are appended as PGN to `games.pgn` in the `library` directory of
+//! [`crate::storage`], while `index.tsv` next to it holds a line per game with the
+//! fields shown in the list and where the game is in the PGN file. Listing the
+//! games only reads the index, so the library stays quick with tens of thousands
+//! of them, and a game's moves are only read once it's opened. Games in the named
+//! save slots of [`crate::saves`] are listed alongside them.
+
+use std::{
+    cmp::Ordering,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context, Result};
+use bevy::prelude::*;
+
+use crate::{
+    game::{tree::GameTree, Board},
+    openings, pgn,
+    saves::{self, SavedGame},
+    storage, GameMode, GameRule,
+};
+
+const GAMES_FILE: &str = "games.pgn";
+const INDEX_FILE: &str = "index.tsv";
+
+/// An import finished in the background, waiting to be picked up by `receive_import`
+static IMPORT: Mutex<Option<Result<Imported, String>>> = Mutex::new(None);
+
+/// Held while a game is appended, so games finished during an import don't end up
+/// in the middle of one of its games
+static WRITING: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    White,
+    Black,
+    Date,
+    Result,
+    Opening,
+    Moves,
+}
+
+impl Column {
+    pub const ALL: [Column; 6] = [
+        Column::White,
+        Column::Black,
+        Column::Date,
+        Column::Result,
+        Column::Opening,
+        Column::Moves,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::White => "White",
+            Column::Black => "Black",
+            Column::Date => "Date",
+            Column::Result => "Result",
+            Column::Opening => "Opening",
+            Column::Moves => "Moves",
+        }
+    }
+}
+
+/// Where a listed game is kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Location {
+    /// In the games file, where its PGN is `length` bytes starting `offset` bytes in
+    File { offset: u64, length: u64 },
+    /// In the save slot of this name
+    Slot(String),
+}
+
+/// A game as listed in the index, or a saved game
+#[derive(Debug, Clone)]
+pub struct Entry {
+    location: Location,
+    pub white: String,
+    pub black: String,
+    pub date: String,
+    pub result: String,
+    pub opening: String,
+    /// Full moves in the main line
+    pub moves: usize,
+}
+
+impl Entry {
+    fn new(tree: &GameTree, location: Location) -> Entry {
+        let end = tree.main_line_end();
+        let tag = |name| tree.tag(name).unwrap_or("?").to_string();
+        Entry {
+            location,
+            white: tag("White"),
+            black: tag("Black"),
+            date: tree.tag("Date").unwrap_or("????.??.??").to_string(),
+            result: tree.tag("Result").unwrap_or("*").to_string(),
+            opening: openings::classify(tree, end)
+                .map(|opening| format!("{} {}", opening.eco, opening.name))
+                .or_else(|| tree.tag("Opening").map(String::from))
+                .unwrap_or_default(),
+            moves: tree.ply(end).div_ceil(2),
+        }
+    }
+
+    /// The value shown in `column`
+    pub fn text(&self, column: Column) -> String {
+        match column {
+            Column::White => self.white.clone(),
+            Column::Black => self.black.clone(),
+            Column::Date => self.date.clone(),
+            Column::Result => self.result.clone(),
+            Column::Opening => self.opening.clone(),
+            Column::Moves => self.moves.to_string(),
+        }
+    }
+
+    fn compare(&self, other: &Entry, column: Column) -> Ordering {
+        let ignore_case = |a: &str, b: &str| {
+            a.chars()
+                .flat_map(char::to_lowercase)
+                .cmp(b.chars().flat_map(char::to_lowercase))
+        };
+        match column {
+            Column::White => ignore_case(&self.white, &other.white),
+            Column::Black => ignore_case(&self.black, &other.black),
+            // PGN dates sort as written
+            Column::Date => self.date.cmp(&other.date),
+            Column::Result => self.result.cmp(&other.result),
+            Column::Opening => ignore_case(&self.opening, &other.opening),
+            Column::Moves => self.moves.cmp(&other.moves),
+        }
+    }
+
+    /// The entry's line in the index, which only lists games in the games file
+    fn to_line(&self) -> Option<String> {
+        let Location::File { offset, length } = self.location else {
+            return None;
+        };
+        let clean = |value: &str| value.replace(['\t', '\n', '\r'], " ");
+        Some(format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            offset,
+            length,
+            clean(&self.white),
+            clean(&self.black),
+            clean(&self.date),
+            clean(&self.result),
+            clean(&self.opening),
+            self.moves
+        ))
+    }
+
+    fn from_line(line: &str) -> Option<Entry> {
+        let mut fields = line.split('\t');
+        let mut next = || fields.next().map(String::from);
+        Some(Entry {
+            location: Location::File {
+                offset: next()?.parse().ok()?,
+                length: next()?.parse().ok()?,
+            },
+            white: next()?,
+            black: next()?,
+            date: next()?,
+            result: next()?,
+            opening: next()?,
+            moves: next()?.parse().ok()?,
+        })
+    }
+
+    /// The name of the save slot holding the game, if it's a saved game
+    pub fn slot(&self) -> Option<&str> {
+        match &self.location {
+            Location::Slot(name) => Some(name),
+            Location::File { .. } => None,
+        }
+    }
+}
+
+fn directory() -> Result<PathBuf> {
+    storage::directory("library").map_err(|e| anyhow!("the game library is {e}"))
+}
+
+/// The games file and the index, opened for appending
+struct Writer {
+    games: File,
+    index: File,
+}
+
+impl Writer {
+    fn open() -> Result<Writer> {
+        let dir = directory()?;
+        let append = |name| {
+            let path = dir.join(name);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("couldn't open {}", path.display()))
+        };
+        Ok(Writer {
+            games: append(GAMES_FILE)?,
+            index: append(INDEX_FILE)?,
+        })
+    }
+
+    fn append(&mut self, tree: &GameTree) -> Result<()> {
+        let pgn = pgn::export(tree);
+        let _writing = WRITING.lock().expect("Mutex should never be poisoned");
+        let offset = self.games.seek(SeekFrom::End(0))?;
+        self.games.write_all(format!("{pgn}\n").as_bytes())?;
+        let length = pgn.len() as u64;
+        let entry = Entry::new(tree, Location::File { offset, length });
+        if let Some(line) = entry.to_line() {
+            self.index.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn read_index() -> Result<Vec<Entry>> {
+    let path = directory()?.join(INDEX_FILE);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("couldn't open {}", path.display())),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| Entry::from_line(&line))
+        .collect())
+}
+
+/// The games in the named save slots
+fn read_slots() -> Vec<Entry> {
+    saves::names()
+        .into_iter()
+        .filter_map(|name| {
+            let tree = SavedGame::load(&name)?.tree()?;
+            Some(Entry::new(&tree, Location::Slot(name)))
+        })
+        .collect()
+}
+
+/// Reads the game listed as `entry`, only the main line for games in the games file
+fn read_game(entry: &Entry) -> Result<GameTree> {
+    let (offset, length) = match &entry.location {
+        Location::File { offset, length } => (*offset, *length),
+        Location::Slot(name) => {
+            return SavedGame::load(name)
+                .and_then(|saved| saved.tree())
+                .with_context(|| format!("the saved game \"{name}\" couldn't be read"));
+        }
+    };
+    let path = directory()?.join(GAMES_FILE);
+    let mut file =
+        File::open(&path).with_context(|| format!("couldn't open {}", path.display()))?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut text = vec![0; length as usize];
+    file.read_exact(&mut text)?;
+    pgn::Reader::new(text.as_slice())
+        .next()
+        .and_then(|game| game.to_tree())
+        .context("the game couldn't be read")
+}
+
+/// Reads the first game of the PGN file at `path`, without adding it to the library
+pub fn read_file(path: &str) -> Result<GameTree, String> {
+    let file = File::open(path).map_err(|e| format!("couldn't open {path}: {e}"))?;
+    pgn::Reader::new(BufReader::new(file))
+        .next()
+        .and_then(|game| game.to_tree())
+        .ok_or_else(|| format!("the first game of {path} couldn't be read"))
+}
+
+/// Games imported from a PGN file, and those that couldn't be read
+#[derive(Debug, Default)]
+struct Imported {
+    added: usize,
+    skipped: usize,
+}
+
+fn import(path: &str) -> Result<Imported> {
+    let file = File::open(path).with_context(|| format!("couldn't open {}", path))?;
+    let mut writer = Writer::open()?;
+    let mut imported = Imported::default();
+    for game in pgn::Reader::new(BufReader::new(file)) {
+        match game.to_tree() {
+            Some(tree) => {
+                writer.append(&tree)?;
+                imported.added += 1;
+            }
+            None => imported.skipped += 1,
+        }
+    }
+    Ok(imported)
+}
+
+/// The players of a game in `mode`, if the mode has any
+fn players(mode: &GameMode, rule: &GameRule) -> Option<(String, String)> {
+    let engine = rule.engine.name();
+    let player = String::from("Player");
+    match mode {
+        GameMode::Hotseat => Some((player.clone(), player)),
+        GameMode::VsAi => Some(match rule.ai_color {
+            chess::Color::White => (engine, player),
+            chess::Color::Black => (player, engine),
+        }),
+        GameMode::Sim => Some((engine.clone(), engine)),
+        GameMode::Analysis | GameMode::Puzzle | GameMode::Replay => None,
+    }
+}
+
+/// Today's date as written in the PGN `Date` tag
+#[cfg(not(target_arch = "wasm32"))]
+fn today() -> Option<String> {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    // days since 1970 to a date, after Howard Hinnant's `civil_from_days`
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    Some(format!("{year:04}.{month:02}.{day:02}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn today() -> Option<String> {
+    None
+}
+
+/// Adds the game to the library, first filling in the players and date if the
+/// game doesn't name them
+pub fn add(tree: &mut GameTree, mode: &GameMode, rule: &GameRule) -> Result<(), String> {
+    if let Some((white, black)) = players(mode, rule) {
+        for (name, value) in [("White", white), ("Black", black)] {
+            if tree.tag(name).is_none() {
+                tree.set_tag(name, value);
+            }
+        }
+    }
+    if tree.tag("Date").is_none() {
+        if let Some(date) = today() {
+            tree.set_tag("Date", date);
+        }
+    }
+    Writer::open()
+        .and_then(|mut writer| writer.append(tree))
+        .map_err(|e| format!("{e:#}"))
+}
+
+pub fn add_finished_game(
+    mut board: ResMut<Board>,
+    game_mode: Res<State<GameMode>>,
+    game_rule: Res<GameRule>,
+) {
+    if let Err(e) = add(board.tree_mut(), &game_mode, &game_rule) {
+        warn!("Couldn't add the game to the library: {e}");
+    }
+}
+
+/// The library as listed on its screen
+#[derive(Resource)]
+pub struct Library {
+    pub entries: Vec<Entry>,
+    /// Indices into `entries` of the games passing the filters, in sorted order
+    pub shown: Vec<usize>,
+    pub sort: Column,
+    pub descending: bool,
+    /// Matched against either player
+    pub player: String,
+    pub opening: String,
+    pub date: String,
+    pub result: Option<&'static str>,
+    pub import_path: String,
+    pub importing: bool,
+    /// The outcome of the last import
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            shown: Vec::new(),
+            sort: Column::Date,
+            descending: true,
+            player: String::new(),
+            opening: String::new(),
+            date: String::new(),
+            result: None,
+            import_path: String::from("games.pgn"),
+            importing: false,
+            message: None,
+            error: None,
+        }
+    }
+}
+
+impl Library {
+    /// Reads the index and the save slots again
+    pub fn reload(&mut self) {
+        match read_index() {
+            Ok(entries) => {
+                self.entries = entries;
+                self.error = None;
+            }
+            Err(e) => {
+                self.entries.clear();
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+        self.entries.extend(read_slots());
+        self.update_shown();
+    }
+
+    /// Filters and sorts the entries again after the filters or sort order changed
+    pub fn update_shown(&mut self) {
+        let player = self.player.to_lowercase();
+        let opening = self.opening.to_lowercase();
+        let contains = |value: &str, part: &str| value.to_lowercase().contains(part);
+        let mut shown: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| {
+                let entry = &self.entries[i];
+                (contains(&entry.white, &player) || contains(&entry.black, &player))
+                    && contains(&entry.opening, &opening)
+                    && entry.date.contains(&self.date)
+                    && self.result.map_or(true, |result| entry.result == result)
+            })
+            .collect();
+        shown.sort_by(|&a, &b| {
+            let order = self.entries[a].compare(&self.entries[b], self.sort);
+            if self.descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        self.shown = shown;
+    }
+
+    /// Sorts by `column`, or reverses the order if the entries are already sorted by it
+    pub fn sort_by(&mut self, column: Column) {
+        if self.sort == column {
+            self.descending = !self.descending;
+        } else {
+            self.sort = column;
+            self.descending = false;
+        }
+        self.update_shown();
+    }
+
+    /// Reads the game at `index` in `entries`
+    pub fn open(&self, index: usize) -> Result<GameTree, String> {
+        read_game(&self.entries[index]).map_err(|e| format!("{e:#}"))
+    }
+
+    /// Adds every game of the PGN file at `import_path` in the background
+    pub fn start_import(&mut self) {
+        self.importing = true;
+        self.message = None;
+        self.error = None;
+        let path = self.import_path.clone();
+        let import = move || {
+            let imported = import(&path).map_err(|e| format!("{e:#}"));
+            *IMPORT.lock().expect("Mutex should never be poisoned") = Some(imported);
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(import);
+        #[cfg(target_arch = "wasm32")]
+        import();
+    }
+}
+
+pub fn load_library(mut library: ResMut<Library>) {
+    library.reload();
+}
+
+pub fn receive_import(mut library: ResMut<Library>) {
+    if !library.importing {
+        return;
+    }
+    let Some(imported) = IMPORT
+        .lock()
+        .expect("Mutex should never be poisoned")
+        .take()
+    else {
+        return;
+    };
+    library.importing = false;
+    match imported {
+        Ok(imported) => {
+            library.message = Some(format!(
+                "Imported {} games, skipped {} that couldn't be read",
+                imported.added, imported.skipped
+            ));
+            library.reload();
+        }
+        Err(e) => {
+            // games read before the error are kept
+            library.reload();
+            library.error = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::parse_uci;
+
+    fn game() -> GameTree {
+        let mut tree = GameTree::default();
+        for uci in ["e2e4", "e7e5", "g1f3"] {
+            tree.play(parse_uci(uci).unwrap());
+        }
+        tree.set_tag("White", "Tab\tbed");
+        tree.set_tag("Result", "1-0");
+        tree
+    }
+
+    #[test]
+    fn index_lines_read_back() {
+        let location = Location::File {
+            offset: 120,
+            length: 80,
+        };
+        let entry = Entry::new(&game(), location.clone());
+        assert_eq!(entry.moves, 2);
+        assert_eq!(entry.black, "?");
+
+        let read = Entry::from_line(entry.to_line().unwrap().trim_end()).unwrap();
+        assert_eq!(read.location, location);
+        assert_eq!(read.white, "Tab bed");
+        assert_eq!(read.result, "1-0");
+        assert_eq!(read.moves, 2);
+        assert_eq!(read.slot(), None);
+    }
+
+    #[test]
+    fn saved_games_stay_out_of_the_index() {
+        let entry = Entry::new(&game(), Location::Slot(String::from("Mine")));
+        assert_eq!(entry.slot(), Some("Mine"));
+        assert_eq!(entry.to_line(), None);
+    }
+
+    #[test]
+    fn entries_sort_by_column() {
+        let mut a = Entry::new(&game(), Location::Slot(String::from("a")));
+        let b = a.clone();
+        a.white = String::from("alice");
+        assert_eq!(a.compare(&b, Column::White), Ordering::Greater);
+        assert_eq!(a.compare(&b, Column::Moves), Ordering::Equal);
+    }
+}
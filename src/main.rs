@@ -8,6 +8,7 @@ use bevy_egui::{egui, EguiContexts};
 use shake_chess::GameState;
 
 fn main() -> Result<()> {
+    let settings = shake_chess::settings::Settings::load();
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
@@ -21,37 +22,72 @@ fn main() -> Result<()> {
         .add_plugins(bevy_svg::prelude::SvgPlugin)
         .add_plugins(bevy_egui::EguiPlugin)
         .insert_resource(ClearColor(
-            Srgba::hex(shake_chess::render::BACKGROUND_COLOR)?.into(),
+            Srgba::hex(settings.palette().background)?.into(),
         ))
         .insert_resource(shake_chess::render::DrawInfo::default())
+        .insert_resource(settings.animation())
         .insert_resource(shake_chess::game::Board::default())
         .insert_resource(shake_chess::game::PointedSquare::default())
         .insert_resource(shake_chess::game::SelectedPiece::None)
         .insert_resource(shake_chess::SideToMove(chess::Color::White))
-        .insert_resource(shake_chess::SwitchSides(true))
-        .insert_resource(shake_chess::GameRule::default())
+        .insert_resource(shake_chess::SwitchSides(settings.auto_rotate))
+        .insert_resource(shake_chess::FlipBoard::default())
+        .insert_resource(settings.coordinates())
+        .insert_resource(settings.theme())
+        .insert_resource(shake_chess::annotation::Annotations::default())
+        .insert_resource(settings.audio())
+        .insert_resource(shake_chess::ui::ShowSettings::default())
+        .insert_resource(settings.game_rule())
         .insert_resource(shake_chess::Last50::default())
         .insert_resource(shake_chess::GameResult::default())
+        .insert_resource(shake_chess::editor::Editor::default())
+        .insert_resource(shake_chess::puzzle::PuzzleSession::default())
+        .insert_resource(shake_chess::explorer::Explorer::default())
+        .insert_resource(shake_chess::openings::CurrentOpening::default())
+        .insert_resource(shake_chess::saves::SaveSlots::default())
+        .insert_resource(shake_chess::library::Library::default())
+        .insert_resource(shake_chess::replay::Replay::default())
+        .insert_resource(shake_chess::game::DrawOffer::default())
+        .insert_resource(shake_chess::ai::Evaluation::default())
+        .insert_resource(settings)
         .init_state::<GameState>()
         .init_state::<shake_chess::GameMode>()
         .add_event::<shake_chess::TurnEndEvent>()
+        .add_event::<shake_chess::audio::PlaySound>()
         .add_systems(PreStartup, shake_chess::render::update_draw_info)
-        .add_systems(Startup, (setup, setup_ui, shake_chess::game::setup_game))
+        .add_systems(
+            Startup,
+            (
+                setup,
+                setup_ui,
+                shake_chess::game::setup_game,
+                shake_chess::render::setup_check_glow,
+                shake_chess::audio::load_sounds,
+            ),
+        )
         .add_systems(PreUpdate, shake_chess::render::update_draw_info)
         .add_systems(
             Update,
             (
                 (
                     shake_chess::render::draw_chessboard,
-                    shake_chess::render::draw_pieces,
+                    shake_chess::render::draw_pieces.run_if(not(in_state(GameState::Editor))),
                 )
                     .chain(),
+                shake_chess::render::draw_pockets.run_if(not(in_state(GameState::Editor))),
+                shake_chess::render::render_highlights.run_if(not(in_state(GameState::Editor))),
+                shake_chess::render::draw_coordinates.run_if(not(in_state(GameState::Editor))),
+                shake_chess::render::render_annotations.run_if(not(in_state(GameState::Editor))),
                 (
                     shake_chess::game::mouse_point,
                     shake_chess::game::act,
+                    shake_chess::annotation::annotate,
                     shake_chess::ui::turn_readout,
+                    shake_chess::ui::promotion_picker,
+                    shake_chess::openings::recognize_opening,
                     shake_chess::render::cursor_swap,
                     shake_chess::render::render_selector,
+                    shake_chess::render::spawn_capture_effects,
                     shake_chess::ai::stockfish_move.run_if(
                         in_state(shake_chess::GameState::Playing)
                             .and(in_state(shake_chess::GameMode::VsAi)),
@@ -60,15 +96,85 @@ fn main() -> Result<()> {
                         in_state(shake_chess::GameState::Playing)
                             .and(in_state(shake_chess::GameMode::Sim)),
                     ),
+                    shake_chess::ui::move_tree
+                        .run_if(in_state(shake_chess::GameMode::Analysis))
+                        .before(shake_chess::render::draw_chessboard),
+                    (
+                        shake_chess::puzzle::follow_solution,
+                        shake_chess::ui::puzzle_panel.before(shake_chess::render::draw_chessboard),
+                    )
+                        .run_if(in_state(shake_chess::GameMode::Puzzle)),
+                    (
+                        shake_chess::ui::replay_panel.before(shake_chess::render::draw_chessboard),
+                        shake_chess::replay::autoplay,
+                    )
+                        .run_if(in_state(shake_chess::GameMode::Replay)),
                     toggle_switch_sides,
+                    toggle_flip_board,
+                    shake_chess::game::step_through_moves,
                 )
                     .run_if(in_state(GameState::Playing)),
             )
-                .run_if(not(in_state(GameState::MainMenu))),
+                .run_if(not(in_state(GameState::MainMenu)).and(not(in_state(GameState::Library)))),
+        )
+        .add_systems(
+            Update,
+            (
+                shake_chess::render::animate_capture_effects,
+                shake_chess::render::animate_slides,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                shake_chess::explorer::receive_import,
+                shake_chess::library::receive_import,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                shake_chess::audio::move_sounds,
+                shake_chess::audio::play_sounds,
+            )
+                .chain(),
+        )
+        .add_systems(
+            OnEnter(GameState::End),
+            (
+                shake_chess::audio::game_end_sound,
+                shake_chess::saves::clear_autosave,
+                shake_chess::library::add_finished_game,
+                shake_chess::game::clear_draw_offer,
+            ),
+        )
+        .add_systems(
+            Update,
+            shake_chess::saves::autosave.run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            OnEnter(GameState::MainMenu),
+            (
+                shake_chess::saves::refresh_slots,
+                shake_chess::game::clear_draw_offer,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                shake_chess::theme::apply_theme,
+                shake_chess::ui::settings_window,
+                shake_chess::settings::save_settings,
+            ),
         )
         .add_systems(
             PostUpdate,
-            shake_chess::game::check_end.run_if(in_state(GameState::Playing)),
+            shake_chess::game::check_end.run_if(
+                in_state(GameState::Playing)
+                    .and(not(in_state(shake_chess::GameMode::Analysis)))
+                    .and(not(in_state(shake_chess::GameMode::Puzzle)))
+                    .and(not(in_state(shake_chess::GameMode::Replay))),
+            ),
         )
         .add_systems(
             Update,
@@ -76,8 +182,39 @@ fn main() -> Result<()> {
         )
         .add_systems(
             Update,
-            shake_chess::ui::end_screen.run_if(in_state(GameState::End)),
+            // menus despawning the board have to do so before it's redrawn, or the
+            // redraw updates sprites that are about to be removed
+            shake_chess::ui::end_screen
+                .run_if(in_state(GameState::End))
+                .before(shake_chess::render::draw_chessboard),
+        )
+        .add_systems(
+            Update,
+            shake_chess::ui::library_screen.run_if(in_state(GameState::Library)),
+        )
+        .add_systems(
+            OnEnter(GameState::Library),
+            shake_chess::library::load_library,
         )
+        .add_systems(
+            Update,
+            (
+                (
+                    shake_chess::game::mouse_point,
+                    shake_chess::editor::editor_input,
+                    shake_chess::editor::draw_editor,
+                    shake_chess::editor::move_held_piece,
+                )
+                    .chain(),
+                shake_chess::ui::board_editor.before(shake_chess::render::draw_chessboard),
+            )
+                .run_if(in_state(GameState::Editor)),
+        )
+        .add_systems(
+            OnEnter(GameState::Editor),
+            shake_chess::editor::enter_editor,
+        )
+        .add_systems(OnExit(GameState::Editor), shake_chess::editor::exit_editor)
         .run();
 
     Ok(())
@@ -117,12 +254,33 @@ pub fn setup_ui(mut contexts: EguiContexts) {
 }
 
 fn toggle_switch_sides(
+    mut contexts: EguiContexts,
     mut up_ev: EventWriter<shake_chess::TurnEndEvent>,
     mut switch_sides: ResMut<shake_chess::SwitchSides>,
     input: Res<ButtonInput<KeyCode>>,
 ) {
+    // spaces typed into a save name aren't meant for the board
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
     if input.just_pressed(KeyCode::Space) {
         switch_sides.0 = !switch_sides.0;
         up_ev.send(shake_chess::TurnEndEvent);
     }
 }
+
+fn toggle_flip_board(
+    mut contexts: EguiContexts,
+    mut up_ev: EventWriter<shake_chess::TurnEndEvent>,
+    mut flip_board: ResMut<shake_chess::FlipBoard>,
+    input: Res<ButtonInput<KeyCode>>,
+) {
+    // don't flip while typing a path into a text field
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if input.just_pressed(KeyCode::KeyF) {
+        flip_board.0 = !flip_board.0;
+        up_ev.send(shake_chess::TurnEndEvent);
+    }
+}
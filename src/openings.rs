@@ -0,0 +1,106 @@
+//! Opening names from an ECO table in the format of the public chess-openings TSV files
+//!
+//! Each line of `assets/openings.tsv` holds an ECO code, a name and the moves leading
+//! to the named position. The bundled table is a selection of about a hundred
+//! common openings rather than the full files, so many positions those name stay
+//! unnamed here; the full files can be appended to it as they are. Positions are
+//! matched by their hash, so openings reached through transpositions are
+//! recognized too.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use bevy::prelude::*;
+
+use crate::{
+    game::{
+        tree::{GameTree, NodeId},
+        Board,
+    },
+    pgn,
+    variant::Variant,
+    TurnEndEvent,
+};
+
+const TABLE: &str = include_str!("../assets/openings.tsv");
+
+static OPENINGS: OnceLock<HashMap<u64, Opening>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opening {
+    pub eco: &'static str,
+    pub name: &'static str,
+}
+
+/// The named positions, parsed from the table the first time they are needed
+fn openings() -> &'static HashMap<u64, Opening> {
+    OPENINGS.get_or_init(|| {
+        let mut openings = HashMap::new();
+        'lines: for line in TABLE.lines().skip(1) {
+            let mut fields = line.split('\t');
+            let (Some(eco), Some(name), Some(movetext)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let moves = pgn::Reader::new(movetext.as_bytes())
+                .next()
+                .map(|game| game.moves)
+                .unwrap_or_default();
+            let mut board = chess::Board::default();
+            for san in moves.iter() {
+                match pgn::parse_san(&board, san) {
+                    Some(m) => board = board.make_move_new(m),
+                    None => {
+                        warn!("Illegal move {} in opening {} {}", san, eco, name);
+                        continue 'lines;
+                    }
+                }
+            }
+            openings.insert(board.get_hash(), Opening { eco, name });
+        }
+        openings
+    })
+}
+
+/// How many openings the table names
+pub fn count() -> usize {
+    openings().len()
+}
+
+/// The most specific named opening on the way to `id`
+pub fn classify(tree: &GameTree, id: NodeId) -> Option<Opening> {
+    if tree.variant() != Variant::Standard {
+        return None;
+    }
+    tree.path(id).into_iter().rev().find_map(|node| {
+        let board = tree.node(node).board.to_board()?;
+        openings().get(&board.get_hash()).copied()
+    })
+}
+
+/// The opening of the position on the board
+#[derive(Resource, Default)]
+pub struct CurrentOpening(pub Option<Opening>);
+
+/// Names the opening after every move and tags the game with the opening of its main line
+pub fn recognize_opening(
+    mut up_ev: EventReader<TurnEndEvent>,
+    mut board: ResMut<Board>,
+    mut current: ResMut<CurrentOpening>,
+) {
+    if up_ev.is_empty() {
+        return;
+    }
+    for _ in up_ev.read() {}
+
+    let tree = board.tree();
+    current.0 = classify(tree, tree.current());
+    let Some(opening) = classify(tree, tree.main_line_end()) else {
+        return;
+    };
+    // setting the same tags again would still count as a change to the board
+    if tree.tag("ECO") != Some(opening.eco) || tree.tag("Opening") != Some(opening.name) {
+        board.tree_mut().set_tag("ECO", opening.eco);
+        board.tree_mut().set_tag("Opening", opening.name);
+    }
+}
@@ -0,0 +1,610 @@
+use chess::{ChessMove, Piece};
+
+use crate::{
+    game::tree::{fen_fullmove_number, fen_halfmove_clock, GameTree, Node, NodeId},
+    variant::{chess960, crazyhouse, Position, Variant},
+};
+
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+const MAX_LINE_LENGTH: usize = 80;
+
+fn file_char(square: chess::Square) -> char {
+    (b'a' + square.get_file().to_index() as u8) as char
+}
+
+fn rank_char(square: chess::Square) -> char {
+    (b'1' + square.get_rank().to_index() as u8) as char
+}
+
+/// Writes `m` in standard algebraic notation, including check and mate suffixes
+///
+/// The move is assumed to be one of the `legal` moves on `board`, while `check`
+/// and `checkmate` describe the resulting position under the rules of the variant.
+/// Chess960 castling is recognized by the king moving onto its own rook and
+/// Crazyhouse drops are written as `N@e4`.
+pub fn san(
+    board: &Position,
+    m: ChessMove,
+    legal: &[ChessMove],
+    check: bool,
+    checkmate: bool,
+) -> String {
+    if let Some((piece, square)) = crazyhouse::as_drop(m) {
+        let mut san = format!("{}@{}", piece.to_string(chess::Color::White), square);
+        push_check(&mut san, check, checkmate);
+        return san;
+    }
+
+    let source = m.get_source();
+    let dest = m.get_dest();
+    let piece = board.piece_on(source).unwrap();
+    let file_distance = source.get_file().to_index() as i32 - dest.get_file().to_index() as i32;
+    let castles = piece == Piece::King
+        && (file_distance.abs() == 2 || board.color_on(dest) == Some(board.side_to_move()));
+
+    let mut san = String::new();
+    if castles {
+        san.push_str(if file_distance < 0 { "O-O" } else { "O-O-O" });
+    } else if piece == Piece::Pawn {
+        if file_distance != 0 {
+            san.push(file_char(source));
+            san.push('x');
+        }
+        san.push_str(&dest.to_string());
+        if let Some(promotion) = m.get_promotion() {
+            san.push('=');
+            san.push_str(&promotion.to_string(chess::Color::White));
+        }
+    } else {
+        san.push_str(&piece.to_string(chess::Color::White));
+
+        let others: Vec<chess::Square> = legal
+            .iter()
+            .filter(|o| {
+                o.get_dest() == dest
+                    && o.get_source() != source
+                    && board.piece_on(o.get_source()) == Some(piece)
+            })
+            .map(|o| o.get_source())
+            .collect();
+        if !others.is_empty() {
+            let shares_file = others.iter().any(|s| s.get_file() == source.get_file());
+            let shares_rank = others.iter().any(|s| s.get_rank() == source.get_rank());
+            if !shares_file {
+                san.push(file_char(source));
+            } else if !shares_rank {
+                san.push(rank_char(source));
+            } else {
+                san.push(file_char(source));
+                san.push(rank_char(source));
+            }
+        }
+
+        if board.piece_on(dest).is_some() {
+            san.push('x');
+        }
+        san.push_str(&dest.to_string());
+    }
+
+    push_check(&mut san, check, checkmate);
+    san
+}
+
+fn push_check(san: &mut String, check: bool, checkmate: bool) {
+    if checkmate {
+        san.push('#');
+    } else if check {
+        san.push('+');
+    }
+}
+
+/// The conventional symbol for the most common annotation glyphs
+pub fn nag_symbol(nag: u8) -> Option<&'static str> {
+    match nag {
+        1 => Some("!"),
+        2 => Some("?"),
+        3 => Some("!!"),
+        4 => Some("??"),
+        5 => Some("!?"),
+        6 => Some("?!"),
+        _ => None,
+    }
+}
+
+/// The move number and whether it is White's move for the move leading to `id`
+pub fn move_number(tree: &GameTree, id: NodeId) -> (usize, bool) {
+    let black_starts = tree.start_board().side_to_move() == chess::Color::Black;
+    let ply = tree.ply(id) - 1 + black_starts as usize;
+    (ply / 2 + 1, ply % 2 == 0)
+}
+
+/// Exports the whole tree, variations and annotations included, as PGN
+pub fn export(tree: &GameTree) -> String {
+    let mut out = String::new();
+    for (name, default) in SEVEN_TAG_ROSTER {
+        let value = tree.tag(name).unwrap_or(default);
+        out.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+    }
+    let (variant_start, variant_state) = tree.variant().start_position(chess960::STANDARD_POSITION);
+    let root = tree.node(GameTree::ROOT);
+    if tree.variant() == Variant::Chess960
+        || root.board != variant_start
+        || root.state != variant_state
+    {
+        out.push_str("[SetUp \"1\"]\n");
+        out.push_str(&format!("[FEN \"{}\"]\n", tree.fen(GameTree::ROOT, false)));
+    }
+    for (name, value) in tree.tags() {
+        let reserved =
+            SEVEN_TAG_ROSTER.iter().any(|(n, _)| n == name) || name == "SetUp" || name == "FEN";
+        if !reserved {
+            out.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+        }
+    }
+    out.push('\n');
+
+    let mut tokens = Vec::new();
+    if let Some(comment) = comment(tree.node(GameTree::ROOT)) {
+        tokens.push(comment);
+    }
+    write_line(tree, GameTree::ROOT, &mut tokens, true);
+    tokens.push(tree.tag("Result").unwrap_or("*").to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() + 1 > MAX_LINE_LENGTH {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    out.push_str(&line);
+    out.push('\n');
+    out
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes the continuation after `from`, with each alternative in parentheses
+fn write_line(tree: &GameTree, from: NodeId, tokens: &mut Vec<String>, mut force_number: bool) {
+    let mut node = from;
+    while let Some((&main, variations)) = tree.node(node).children.split_first() {
+        force_number = write_move(tree, main, tokens, force_number);
+        for &variation in variations {
+            let start = tokens.len();
+            write_move(tree, variation, tokens, true);
+            write_line(tree, variation, tokens, false);
+            tokens[start].insert(0, '(');
+            tokens.last_mut().unwrap().push(')');
+            force_number = true;
+        }
+        node = main;
+    }
+}
+
+/// Writes a single move with its annotations
+///
+/// Returns true if the next move has to repeat its move number.
+fn write_move(tree: &GameTree, id: NodeId, tokens: &mut Vec<String>, force_number: bool) -> bool {
+    let node = tree.node(id);
+    let (number, white) = move_number(tree, id);
+    if white {
+        tokens.push(format!("{}.", number));
+    } else if force_number {
+        tokens.push(format!("{}...", number));
+    }
+    tokens.push(node.san.clone());
+    for nag in node.nags.iter() {
+        tokens.push(format!("${}", nag));
+    }
+    if let Some(comment) = comment(node) {
+        tokens.push(comment);
+        return true;
+    }
+    false
+}
+
+/// The node's comment in braces, led by its circles and arrows as `[%csl]` and
+/// `[%cal]` commands
+fn comment(node: &Node) -> Option<String> {
+    let (circles, arrows): (Vec<_>, Vec<_>) = node.shapes.iter().partition(|s| s.is_circle());
+    let mut parts = Vec::new();
+    if !circles.is_empty() {
+        let squares: Vec<String> = circles
+            .iter()
+            .map(|s| format!("{}{}", s.color.pgn_char(), s.from))
+            .collect();
+        parts.push(format!("[%csl {}]", squares.join(",")));
+    }
+    if !arrows.is_empty() {
+        let arrows: Vec<String> = arrows
+            .iter()
+            .map(|s| format!("{}{}{}", s.color.pgn_char(), s.from, s.to))
+            .collect();
+        parts.push(format!("[%cal {}]", arrows.join(",")));
+    }
+    // a brace would end the comment early, and PGN has no way to escape it
+    let text = node.comment.replace('}', "");
+    if !text.is_empty() {
+        parts.push(text);
+    }
+    (!parts.is_empty()).then(|| format!("{{{}}}", parts.join(" ")))
+}
+
+/// A game read from a PGN file, keeping only the moves of its main line
+#[derive(Debug, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    /// The main line in standard algebraic notation
+    pub moves: Vec<String>,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Plays the main line into a game tree along with the tags
+    ///
+    /// Returns None if the variant, the start position or one of the moves can't
+    /// be read. Chess960 games have to start from one of the 960 start positions.
+    pub fn to_tree(&self) -> Option<GameTree> {
+        let variant = match self.tag("Variant") {
+            None | Some("From Position") => Variant::Standard,
+            Some(name) => Variant::ALL
+                .into_iter()
+                .find(|v| v.name().eq_ignore_ascii_case(name))?,
+        };
+        let (start, state) = match self.tag("FEN") {
+            None => variant.start_position(chess960::STANDARD_POSITION),
+            Some(fen) if variant == Variant::Chess960 => {
+                let placement = fen.split(' ').next()?;
+                let number = (0..960).find(|&n| {
+                    let (board, _) = chess960::start_position(n);
+                    board.to_string().split(' ').next() == Some(placement)
+                })?;
+                variant.start_position(number)
+            }
+            Some(fen) => variant.parse_fen(fen).ok()?,
+        };
+
+        let mut tree = GameTree::with_variant(variant, start, state);
+        if let Some(fen) = self.tag("FEN") {
+            tree.set_halfmove_clock(fen_halfmove_clock(fen));
+            tree.set_fullmove_number(fen_fullmove_number(fen));
+        }
+        for (name, value) in self.tags.iter() {
+            if name != "SetUp" && name != "FEN" {
+                tree.set_tag(name, value.clone());
+            }
+        }
+        for san in self.moves.iter() {
+            let node = tree.current_node();
+            let legal = variant.rules().legal_moves(&node.board, &node.state);
+            let m = find_move(&node.board, &legal, san)?;
+            tree.play(m);
+        }
+        Some(tree)
+    }
+}
+
+/// Reads the games of a PGN file one at a time, so large collections don't have
+/// to fit in memory
+///
+/// Comments, variations, annotation glyphs and move numbers are skipped.
+pub struct Reader<R> {
+    lines: std::io::Lines<R>,
+    /// A tag line already read that starts the next game
+    pending: Option<String>,
+}
+
+impl<R: std::io::BufRead> Reader<R> {
+    pub fn new(read: R) -> Self {
+        Self {
+            lines: read.lines(),
+            pending: None,
+        }
+    }
+}
+
+impl<R: std::io::BufRead> Iterator for Reader<R> {
+    type Item = PgnGame;
+
+    fn next(&mut self) -> Option<PgnGame> {
+        let mut game = PgnGame::default();
+        let mut in_moves = false;
+        let mut comment = false;
+        let mut variation = 0;
+        loop {
+            let line = match self.pending.take() {
+                Some(line) => line,
+                None => match self.lines.next() {
+                    Some(Ok(line)) => line,
+                    _ => break,
+                },
+            };
+            let line = line.trim();
+            if !comment && variation == 0 && line.starts_with('[') {
+                if in_moves {
+                    self.pending = Some(line.to_string());
+                    break;
+                }
+                if let Some(tag) = parse_tag(line) {
+                    game.tags.push(tag);
+                }
+                continue;
+            }
+            if line.starts_with('%') || line.is_empty() {
+                continue;
+            }
+            in_moves = true;
+            read_moves(line, &mut comment, &mut variation, &mut game.moves);
+        }
+        (!game.tags.is_empty() || !game.moves.is_empty()).then_some(game)
+    }
+}
+
+/// Parses a tag pair such as `[White "Carlsen, Magnus"]`
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((
+        name.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+/// Collects the main line moves of a line of movetext
+///
+/// `comment` and `variation` carry over comments and variations spanning lines.
+fn read_moves(line: &str, comment: &mut bool, variation: &mut usize, moves: &mut Vec<String>) {
+    let mut token = String::new();
+    let flush = |token: &mut String, moves: &mut Vec<String>| {
+        // move numbers may be written right before the move, as in `1.e4`
+        let san = match token.rfind('.') {
+            Some(dot) => &token[dot + 1..],
+            None => token.as_str(),
+        };
+        let result = matches!(san, "1-0" | "0-1" | "1/2-1/2" | "*");
+        if !san.is_empty() && !san.starts_with('$') && !result {
+            moves.push(san.to_string());
+        }
+        token.clear();
+    };
+    for c in line.chars() {
+        if *comment {
+            *comment = c != '}';
+            continue;
+        }
+        match c {
+            '{' => {
+                flush(&mut token, moves);
+                *comment = true;
+            }
+            // the rest of the line is a comment
+            ';' => break,
+            '(' => {
+                flush(&mut token, moves);
+                *variation += 1;
+            }
+            ')' => {
+                token.clear();
+                *variation = variation.saturating_sub(1);
+            }
+            c if c.is_whitespace() => flush(&mut token, moves),
+            c if *variation == 0 => token.push(c),
+            _ => {}
+        }
+    }
+    flush(&mut token, moves);
+}
+
+/// Finds the move written as `san` in standard algebraic notation
+///
+/// Check marks, annotations and castling written with zeros are tolerated.
+pub fn parse_san(board: &chess::Board, san: &str) -> Option<ChessMove> {
+    let san = san
+        .trim_end_matches(['+', '#', '!', '?'])
+        .replace("0-0-0", "O-O-O")
+        .replace("0-0", "O-O");
+    ChessMove::from_san(board, &san).ok()
+}
+
+/// Finds the move written as `san` among the `legal` moves of a position in any variant
+///
+/// Moves `chess` can read are looked up directly, the rest are matched against the
+/// notation of every legal move.
+fn find_move(board: &Position, legal: &[ChessMove], written: &str) -> Option<ChessMove> {
+    if let Some(m) = board.to_board().and_then(|b| parse_san(&b, written)) {
+        if legal.contains(&m) {
+            return Some(m);
+        }
+    }
+    let written = written
+        .trim_end_matches(['+', '#', '!', '?'])
+        .replace("0-0-0", "O-O-O")
+        .replace("0-0", "O-O");
+    legal
+        .iter()
+        .copied()
+        .find(|&m| san(board, m, legal, false, false) == written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::ai::parse_uci;
+
+    fn assert_san(fen: &str, uci: &str, expected: &str) {
+        let board = chess::Board::from_str(fen).unwrap();
+        let m = parse_uci(uci).unwrap();
+        let mut tree = GameTree::new(board.into());
+        tree.play(m);
+        assert_eq!(tree.current_node().san, expected);
+        assert_eq!(parse_san(&board, expected), Some(m));
+    }
+
+    #[test]
+    fn san_round_trip() {
+        let moves = [
+            ("e2e4", "e4"),
+            ("d7d5", "d5"),
+            ("e4d5", "exd5"),
+            ("d8d5", "Qxd5"),
+            ("b1c3", "Nc3"),
+            ("d5a5", "Qa5"),
+            ("d2d4", "d4"),
+            ("g8f6", "Nf6"),
+            ("g1f3", "Nf3"),
+            ("c8f5", "Bf5"),
+            ("f1c4", "Bc4"),
+            ("e7e6", "e6"),
+            ("e1g1", "O-O"),
+            ("f8b4", "Bb4"),
+            ("f3e5", "Ne5"),
+            ("e8g8", "O-O"),
+        ];
+        let mut tree = GameTree::default();
+        for (uci, expected) in moves {
+            let board = tree.board().chess();
+            let m = parse_uci(uci).unwrap();
+            tree.play(m);
+            assert_eq!(tree.current_node().san, expected);
+            assert_eq!(parse_san(&board, expected), Some(m));
+        }
+    }
+
+    #[test]
+    fn san_disambiguates_and_promotes() {
+        assert_san("k7/8/8/8/4K3/8/8/R6R w - - 0 1", "a1d1", "Rad1");
+        assert_san("7k/8/8/8/4K3/R7/8/R7 w - - 0 1", "a1a2", "R1a2");
+        assert_san("8/P7/8/8/8/8/8/k6K w - - 0 1", "a7a8q", "a8=Q+");
+    }
+
+    #[test]
+    fn reader_follows_the_main_line() {
+        let pgn = "[Event \"First\"]\n[Result \"1-0\"]\n\n\
+            1. e4 {best by test} e5 (1... c5 2. Nf3) 2. Nf3 $1 Nc6 3.Bb5 a6 ; a comment\n\
+            4. 0-0 1-0\n\n\
+            [Event \"Second\"]\n\n1. d4 d5 *\n";
+        let games: Vec<PgnGame> = Reader::new(pgn.as_bytes()).collect();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("Event"), Some("First"));
+        assert_eq!(games[0].tag("Result"), Some("1-0"));
+        assert_eq!(
+            games[0].moves,
+            ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "0-0"]
+        );
+        assert_eq!(games[1].tag("Event"), Some("Second"));
+        assert_eq!(games[1].moves, ["d4", "d5"]);
+        assert!(games[0].to_tree().is_some());
+    }
+
+    #[test]
+    fn export_reads_back() {
+        let mut tree = GameTree::default();
+        for uci in ["e2e4", "e7e5", "g1f3"] {
+            tree.play(parse_uci(uci).unwrap());
+        }
+        tree.current_node_mut().comment = String::from("a {braced} comment");
+        tree.go_to(1);
+        tree.play(parse_uci("c7c5").unwrap());
+        tree.set_tag("White", "Someone \"Quoted\"");
+
+        let pgn = export(&tree);
+        let game = Reader::new(pgn.as_bytes()).next().unwrap();
+        assert_eq!(game.moves, ["e4", "e5", "Nf3"]);
+        assert_eq!(game.tag("White"), Some("Someone \"Quoted\""));
+        let read = game.to_tree().unwrap();
+        let expected: Vec<ChessMove> = ["e2e4", "e7e5", "g1f3"]
+            .into_iter()
+            .map(|uci| parse_uci(uci).unwrap())
+            .collect();
+        assert_eq!(read.moves(), expected);
+    }
+
+    #[test]
+    fn games_missing_a_king_read_back() {
+        let fen = "8/8/8/3p4/8/8/4P3/8 w - - 0 1";
+        let (start, state) = Variant::Antichess.parse_fen(fen).unwrap();
+        let mut tree = GameTree::with_variant(Variant::Antichess, start, state);
+        tree.play(parse_uci("e2e4").unwrap());
+        tree.play(parse_uci("d5e4").unwrap());
+
+        let game = Reader::new(export(&tree).as_bytes()).next().unwrap();
+        assert_eq!(game.tag("FEN"), Some(fen));
+        let read = game.to_tree().unwrap();
+        assert_eq!(read.variant(), Variant::Antichess);
+        assert_eq!(read.moves(), tree.moves());
+    }
+
+    /// Reads `fen` back through a PGN `FEN` tag
+    fn fen_round_trip(variant: Variant, fen: &str) -> GameTree {
+        let pgn = format!(
+            "[Variant \"{}\"]\n[SetUp \"1\"]\n[FEN \"{}\"]\n\n*\n",
+            variant.name(),
+            fen
+        );
+        let tree = Reader::new(pgn.as_bytes())
+            .next()
+            .unwrap()
+            .to_tree()
+            .unwrap();
+        assert_eq!(tree.fen(GameTree::ROOT, false), fen);
+        tree
+    }
+
+    #[test]
+    fn crazyhouse_fens_keep_pockets() {
+        let mut tree =
+            GameTree::with_variant(Variant::Crazyhouse, Position::default(), Default::default());
+        for uci in ["e2e4", "d7d5", "e4d5", "d8d5"] {
+            tree.play(parse_uci(uci).unwrap());
+        }
+        let fen = tree.fen(tree.current(), false);
+        assert!(fen.contains("[Pp]"));
+        let read = fen_round_trip(Variant::Crazyhouse, &fen);
+        assert_eq!(read.current_node().state, tree.current_node().state);
+
+        let read = fen_round_trip(Variant::Crazyhouse, "4k3/8/8/8/8/8/8/Q~3K3[Pn] w - - 0 1");
+        let state = read.current_node().state;
+        assert_eq!(
+            state.promoted,
+            chess::BitBoard::from_square(chess::Square::A1)
+        );
+        assert_eq!(state.in_pocket(chess::Color::White, Piece::Pawn), 1);
+        assert_eq!(state.in_pocket(chess::Color::Black, Piece::Knight), 1);
+    }
+
+    #[test]
+    fn three_check_fens_keep_checks() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 +1+2";
+        let read = fen_round_trip(Variant::ThreeCheck, fen);
+        assert_eq!(read.current_node().state.checks, [1, 2]);
+
+        let (_, state) = Variant::ThreeCheck
+            .parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 2+1 0 1")
+            .unwrap();
+        assert_eq!(state.checks, [1, 2]);
+    }
+}
@@ -0,0 +1,128 @@
+//! Glicko-2 ratings, updated after every puzzle as a rating period of one game
+//!
+//! See Mark Glickman's "Example of the Glicko-2 system" for the steps followed here.
+
+use std::f64::consts::PI;
+
+use miniserde::{Deserialize, Serialize};
+
+/// Converts between the Glicko and Glicko-2 scales
+const SCALE: f64 = 173.7178;
+/// Constrains how much the volatility may change
+const TAU: f64 = 0.75;
+const CONVERGENCE: f64 = 0.000001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: 1500.,
+            deviation: 350.,
+            volatility: 0.06,
+        }
+    }
+}
+
+impl Rating {
+    /// Updates the rating after a single game against an opponent rated `rating`
+    /// with `deviation`, where `score` is 1 for a win and 0 for a loss
+    pub fn update(&mut self, rating: f64, deviation: f64, score: f64) {
+        let mu = (self.rating - 1500.) / SCALE;
+        let phi = self.deviation / SCALE;
+        let opponent_mu = (rating - 1500.) / SCALE;
+        let opponent_phi = deviation / SCALE;
+
+        let g = 1. / (1. + 3. * opponent_phi.powi(2) / PI.powi(2)).sqrt();
+        let expected = 1. / (1. + (-g * (mu - opponent_mu)).exp());
+        let variance = 1. / (g.powi(2) * expected * (1. - expected));
+        let delta = variance * g * (score - expected);
+
+        // the new volatility is the root of `f`, found with the Illinois algorithm
+        let a = self.volatility.powi(2).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta.powi(2) - phi.powi(2) - variance - ex)
+                / (2. * (phi.powi(2) + variance + ex).powi(2))
+                - (x - a) / TAU.powi(2)
+        };
+        let mut lower = a;
+        let mut upper = if delta.powi(2) > phi.powi(2) + variance {
+            (delta.powi(2) - phi.powi(2) - variance).ln()
+        } else {
+            let mut k = 1.;
+            while f(a - k * TAU) < 0. {
+                k += 1.;
+            }
+            a - k * TAU
+        };
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > CONVERGENCE {
+            let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_c = f(c);
+            if f_c * f_upper <= 0. {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.;
+            }
+            upper = c;
+            f_upper = f_c;
+        }
+        let volatility = (lower / 2.).exp();
+
+        let pre_period = (phi.powi(2) + volatility.powi(2)).sqrt();
+        let new_phi = 1. / (1. / pre_period.powi(2) + 1. / variance).sqrt();
+        let new_mu = mu + new_phi.powi(2) * g * (score - expected);
+
+        self.rating = new_mu * SCALE + 1500.;
+        self.deviation = new_phi * SCALE;
+        self.volatility = volatility;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wins_raise_and_losses_lower_the_rating() {
+        let mut win = Rating::default();
+        win.update(1500., 350., 1.);
+        assert!(win.rating > 1500.);
+
+        let mut loss = Rating::default();
+        loss.update(1500., 350., 0.);
+        assert!(loss.rating < 1500.);
+
+        // against an equal opponent both results move the rating by the same amount
+        assert!((win.rating - 1500. - (1500. - loss.rating)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stronger_opponents_are_worth_more() {
+        let mut weak = Rating::default();
+        weak.update(1200., 100., 1.);
+        let mut strong = Rating::default();
+        strong.update(1800., 100., 1.);
+        assert!(strong.rating > weak.rating);
+    }
+
+    #[test]
+    fn deviation_shrinks_with_every_game() {
+        let mut rating = Rating::default();
+        let mut deviation = rating.deviation;
+        for score in [1., 0., 1., 1., 0.] {
+            rating.update(1500., 100., score);
+            assert!(rating.deviation < deviation);
+            assert!(rating.volatility > 0. && rating.volatility < 0.1);
+            deviation = rating.deviation;
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Tactics puzzles in the Lichess puzzle CSV format
+//!
+//! Each line holds `PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,...`.
+//! The FEN is the position before the opponent's move, so the first of the UCI
+//! moves is played automatically and the user finds the rest of the solution.
+
+pub mod glicko;
+
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, BufReader},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use bevy::prelude::*;
+use chess::ChessMove;
+use rand::seq::SliceRandom;
+
+use crate::{
+    game::{
+        tree::{GameTree, NodeId},
+        Board,
+    },
+    storage, SideToMove, TurnEndEvent,
+};
+use glicko::Rating;
+
+/// Loading stops after this many puzzles, as the full Lichess database is several
+/// million lines long
+const MAX_PUZZLES: usize = 200_000;
+/// Puzzles are picked within this distance of the user's rating when possible
+const RATING_WINDOW: f64 = 100.;
+/// The storage key of the user's puzzle rating
+const RATING_KEY: &str = "puzzle_rating";
+
+pub struct Puzzle {
+    pub id: String,
+    pub start: chess::Board,
+    /// The opponent's first move followed by the solution, alternating sides
+    pub moves: Vec<ChessMove>,
+    pub rating: f64,
+    pub deviation: f64,
+    pub themes: Vec<String>,
+}
+
+impl Puzzle {
+    /// Parses a line of the Lichess puzzle CSV, returning None for the header or
+    /// malformed lines
+    pub fn parse(line: &str) -> Option<Puzzle> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < 8 {
+            return None;
+        }
+        let moves = fields[2]
+            .split_whitespace()
+            .map(crate::ai::parse_uci)
+            .collect::<Option<Vec<_>>>()?;
+        if moves.len() < 2 {
+            return None;
+        }
+        Some(Puzzle {
+            id: fields[0].to_string(),
+            start: chess::Board::from_str(fields[1]).ok()?,
+            moves,
+            rating: fields[3].parse().ok()?,
+            deviation: fields[4].parse().ok()?,
+            themes: fields[7].split_whitespace().map(String::from).collect(),
+        })
+    }
+}
+
+/// Reads the puzzles in the CSV file at `path`
+pub fn load(path: &str) -> Result<Vec<Puzzle>> {
+    let file = std::fs::File::open(path).with_context(|| format!("couldn't open {}", path))?;
+    let mut puzzles = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Some(puzzle) = Puzzle::parse(&line?) {
+            puzzles.push(puzzle);
+        }
+        if puzzles.len() >= MAX_PUZZLES {
+            warn!("Only the first {} puzzles were loaded", MAX_PUZZLES);
+            break;
+        }
+    }
+    Ok(puzzles)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleStatus {
+    Solving,
+    /// The last move tried wasn't the solution and was taken back
+    Wrong,
+    Solved,
+}
+
+#[derive(Resource)]
+pub struct PuzzleSession {
+    pub path: String,
+    puzzles: Vec<Puzzle>,
+    /// Every theme found in the loaded puzzles
+    pub themes: BTreeSet<String>,
+    /// Only puzzles with this theme are picked
+    pub theme: Option<String>,
+    current: Option<usize>,
+    /// Number of moves of the current puzzle played so far
+    progress: usize,
+    pub status: PuzzleStatus,
+    /// Set once the rating has been updated for the current puzzle, so only the
+    /// first attempt counts
+    rated: bool,
+    pub rating: Rating,
+    pub error: Option<String>,
+}
+
+impl Default for PuzzleSession {
+    fn default() -> Self {
+        Self {
+            path: String::from("lichess_db_puzzle.csv"),
+            puzzles: Vec::new(),
+            themes: BTreeSet::new(),
+            theme: None,
+            current: None,
+            progress: 0,
+            status: PuzzleStatus::Solving,
+            rated: false,
+            rating: load_rating(),
+            error: None,
+        }
+    }
+}
+
+impl PuzzleSession {
+    /// Replaces the loaded puzzles with those in the file at `path`
+    pub fn load(&mut self) {
+        match load(&self.path) {
+            Ok(puzzles) => {
+                self.themes = puzzles
+                    .iter()
+                    .flat_map(|p| p.themes.iter().cloned())
+                    .collect();
+                if self
+                    .theme
+                    .as_ref()
+                    .is_some_and(|t| !self.themes.contains(t))
+                {
+                    self.theme = None;
+                }
+                info!("Loaded {} puzzles from {}", puzzles.len(), self.path);
+                self.puzzles = puzzles;
+                self.current = None;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        !self.puzzles.is_empty()
+    }
+
+    pub fn puzzle(&self) -> Option<&Puzzle> {
+        self.current.map(|i| &self.puzzles[i])
+    }
+
+    /// The side the user plays in the current puzzle
+    pub fn solver(&self) -> Option<chess::Color> {
+        self.puzzle().map(|p| !p.start.side_to_move())
+    }
+
+    /// Starts a random puzzle with the chosen theme, close to the user's rating
+    ///
+    /// Returns false if no loaded puzzle has the theme.
+    pub fn next(&mut self, board: &mut Board) -> bool {
+        let candidates: Vec<usize> = (0..self.puzzles.len())
+            .filter(|&i| {
+                self.theme
+                    .as_ref()
+                    .map_or(true, |t| self.puzzles[i].themes.contains(t))
+            })
+            .collect();
+        if candidates.is_empty() {
+            self.error = Some(String::from("No puzzles with this theme"));
+            return false;
+        }
+
+        // widen the window until some puzzle falls inside it
+        let mut window = RATING_WINDOW;
+        let choice = loop {
+            let near: Vec<usize> = candidates
+                .iter()
+                .copied()
+                .filter(|&i| (self.puzzles[i].rating - self.rating.rating).abs() <= window)
+                .collect();
+            if let Some(&i) = near.choose(&mut rand::thread_rng()) {
+                break i;
+            }
+            window *= 2.;
+        };
+
+        self.current = Some(choice);
+        self.rated = false;
+        self.error = None;
+        self.retry(board);
+        true
+    }
+
+    /// Sets the current puzzle up from the start again
+    pub fn retry(&mut self, board: &mut Board) {
+        let Some(puzzle) = self.puzzle() else {
+            return;
+        };
+        let (start, first) = (puzzle.start, puzzle.moves[0]);
+        board.reset(start);
+        board.play(first);
+        self.progress = 1;
+        self.status = PuzzleStatus::Solving;
+    }
+
+    /// Updates the rating with the result of the first attempt at the current puzzle
+    fn record(&mut self, solved: bool) {
+        let Some(puzzle) = self.puzzle() else {
+            return;
+        };
+        if self.rated {
+            return;
+        }
+        let (rating, deviation) = (puzzle.rating, puzzle.deviation);
+        self.rating
+            .update(rating, deviation, if solved { 1. } else { 0. });
+        self.rated = true;
+        save_rating(&self.rating);
+    }
+}
+
+/// Checks each move made in a puzzle against the solution and plays the opponent's reply
+///
+/// Only a move made where the solution found so far ends is checked, so stepping
+/// through earlier moves doesn't count as playing them. Wrong moves are taken
+/// back so another one can be tried. Any mating move is accepted, as mate-in-one
+/// puzzles may have several solutions.
+pub fn follow_solution(
+    mut session: ResMut<PuzzleSession>,
+    mut board: ResMut<Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+) {
+    if session.status == PuzzleStatus::Solved {
+        return;
+    }
+    let Some(puzzle) = session.puzzle() else {
+        return;
+    };
+    let tree = board.tree();
+    let node = tree.current_node();
+    let (Some(parent), Some(played)) = (node.parent, node.mv) else {
+        return;
+    };
+    if tree.ply(parent) != session.progress || !on_solution(tree, parent, &puzzle.moves) {
+        return;
+    }
+    let expected = puzzle.moves[session.progress];
+    let moves = puzzle.moves.len();
+    let mate = board.in_check() && board.legal_moves().is_empty();
+
+    if played == expected || mate {
+        session.progress += 1;
+        if session.progress >= moves || mate {
+            session.status = PuzzleStatus::Solved;
+            session.record(true);
+            return;
+        }
+        session.status = PuzzleStatus::Solving;
+        let reply = session.puzzle().unwrap().moves[session.progress];
+        board.play(reply);
+        session.progress += 1;
+    } else {
+        let current = board.tree().current();
+        board.tree_mut().delete(current);
+        session.status = PuzzleStatus::Wrong;
+        session.record(false);
+    }
+    side_to_move.0 = board.side_to_move();
+    up_ev.send(TurnEndEvent);
+}
+
+/// Whether the moves leading to `id` are the first moves of `solution`
+fn on_solution(tree: &GameTree, id: NodeId, solution: &[ChessMove]) -> bool {
+    let moves: Vec<ChessMove> = tree
+        .path(id)
+        .into_iter()
+        .filter_map(|node| tree.node(node).mv)
+        .collect();
+    solution.starts_with(&moves)
+}
+
+fn load_rating() -> Rating {
+    storage::read(RATING_KEY)
+        .and_then(|text| miniserde::json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn save_rating(rating: &Rating) {
+    if let Err(e) = storage::write(RATING_KEY, &miniserde::json::to_string(rating)) {
+        warn!("Couldn't save the puzzle rating: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::parse_uci;
+
+    #[test]
+    fn moves_are_checked_against_the_solution_path() {
+        let solution: Vec<ChessMove> = ["e2e4", "e7e5", "g1f3"]
+            .into_iter()
+            .map(|uci| parse_uci(uci).unwrap())
+            .collect();
+        let mut tree = GameTree::default();
+        let e4 = tree.play(solution[0]);
+        let e5 = tree.play(solution[1]);
+        tree.go_to(e4);
+        let c5 = tree.play(parse_uci("c7c5").unwrap());
+
+        assert!(on_solution(&tree, GameTree::ROOT, &solution));
+        assert!(on_solution(&tree, e5, &solution));
+        assert!(!on_solution(&tree, c5, &solution));
+    }
+}
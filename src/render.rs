@@ -1,19 +1,98 @@
+use std::collections::HashMap;
+
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::window::WindowResized;
+use bevy::render::{
+    render_asset::RenderAssetUsages,
+    render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy::window::{PrimaryWindow, WindowResized};
 use bevy::winit::cursor::CursorIcon;
+use bevy_egui::{egui, EguiContexts};
 use bevy_svg::prelude::*;
 
-use crate::{game::{PointedSquare, SelectedPiece}, TurnEndEvent};
+use crate::{
+    annotation::{Annotations, Shape},
+    game::{cursor_world_position, tree::NodeId, PointedSquare, SelectedPiece},
+    theme::Theme,
+    variant::{atomic, chess960, crazyhouse, Position, Variant},
+    TurnEndEvent,
+};
 
-const SPRITE_SIZE: f32 = 45.;
-const BOARD_LENGTH: i32 = 8;
+pub(crate) const SPRITE_SIZE: f32 = 45.;
+pub(crate) const BOARD_LENGTH: i32 = 8;
 const VERT_BOARD_PERCENT: f32 = 0.90; // the max proportion of the vertical space which the board takes up
 const HORI_BOARD_PERCENT: f32 = 0.90; // ... horizontal
 
-const LIGHT_SQUARE_COLOR: &str = "#F0D9B5"; // stolen from lichess
-const DARK_SQUARE_COLOR: &str = "#B58863"; // ... again
-const SELECT_COLOR: &str = "#ADD8E6";
-pub const BACKGROUND_COLOR: &str = "#313338"; // stolen from discord
+const MOVE_HINT_COLOR: &str = "#88888890";
+const LAST_MOVE_COLOR: &str = "#9BC70069"; // lichess again
+const CHECK_COLOR: [u8; 3] = [231, 0, 0];
+const CHECK_GLOW_RESOLUTION: u32 = 64;
+const POCKET_SLOT_SCALE: f32 = 0.8; // the size of a pocket slot relative to a square
+const POCKET_WIDTH: f32 = 3.; // in pocket slots
+const EXPLOSION_COLOR: &str = "#FF8C00";
+const CAPTURE_EFFECT_SECONDS: f32 = 0.4;
+
+/// True if the board is drawn with Black at the bottom
+///
+/// `switch_sides` turns the board towards the side to move, and `flip_board`
+/// turns it around on top of that.
+pub fn is_flipped(
+    side_to_move: chess::Color,
+    switch_sides: &crate::SwitchSides,
+    flip_board: &crate::FlipBoard,
+) -> bool {
+    (side_to_move == chess::Color::Black && switch_sides.0) != flip_board.0
+}
+
+/// The resources deciding which way up the board is drawn
+#[derive(SystemParam)]
+pub struct BoardView<'w> {
+    side_to_move: Res<'w, crate::SideToMove>,
+    switch_sides: Res<'w, crate::SwitchSides>,
+    flip_board: Res<'w, crate::FlipBoard>,
+}
+
+impl BoardView<'_> {
+    pub fn flipped(&self) -> bool {
+        is_flipped(self.side_to_move.0, &self.switch_sides, &self.flip_board)
+    }
+}
+
+/// Where the file letters and rank numbers are drawn
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coordinates {
+    Off,
+    /// In the corners of the edge squares
+    #[default]
+    Inside,
+    /// Along the edges of the board
+    Outside,
+}
+
+impl Coordinates {
+    pub const ALL: [Coordinates; 3] = [Coordinates::Off, Coordinates::Inside, Coordinates::Outside];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Coordinates::Off => "Off",
+            Coordinates::Inside => "Inside",
+            Coordinates::Outside => "Outside",
+        }
+    }
+}
+
+/// How long a piece takes to slide to its new square, with zero turning animations off
+#[derive(Resource, Debug)]
+pub struct MoveAnimation {
+    pub seconds: f32,
+}
+
+impl Default for MoveAnimation {
+    fn default() -> Self {
+        Self { seconds: 0.2 }
+    }
+}
 
 #[derive(Resource, Default, Debug)]
 pub struct DrawInfo {
@@ -59,73 +138,123 @@ pub fn update_draw_info(
 
 pub fn draw_chessboard(
     draw_info: Res<DrawInfo>,
-    entities: Query<Entity, With<crate::Square>>,
+    mut squares: Query<(&crate::Square, &mut Transform)>,
     mut commands: Commands,
     mut window_ev: EventReader<WindowResized>,
     mut up_ev: EventReader<TurnEndEvent>,
-    side_to_move: Res<crate::SideToMove>,
-    switch_sides: Res<crate::SwitchSides>,
+    view: BoardView,
+    theme: Res<Theme>,
 ) {
     if up_ev.is_empty() && window_ev.is_empty() {
         return;
     }
     for _ in up_ev.read() {}
     for _ in window_ev.read() {}
-    debug!("Redrawing board...");
-
-    for entity in entities.iter() {
-        commands.entity(entity).despawn();
-    }
 
-    let offset = -draw_info.square_size * BOARD_LENGTH as f32 / 2.;
-    commands.spawn((
-        Sprite {
-            color: Srgba::hex(LIGHT_SQUARE_COLOR).unwrap().into(),
-            custom_size: Some(Vec2::new(
-                draw_info.square_size * BOARD_LENGTH as f32,
-                draw_info.square_size * BOARD_LENGTH as f32,
-            )),
-            ..default()
-        },
-        Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-        crate::Square,
-    ));
-
-    let is_white = side_to_move.0 == chess::Color::White;
-    let draw_offset_divisor = if !is_white && switch_sides.0 { 0 } else { 1 };
-
-    for i in 0..BOARD_LENGTH {
-        for j in 0..BOARD_LENGTH {
-            if (i + j) % 2 == draw_offset_divisor {
-                continue;
-            }
+    // squares are spawned once, then only moved when the board is resized or flipped
+    let flipped = view.flipped();
+    if squares.is_empty() {
+        debug!("Spawning board...");
+        for square in chess::ALL_SQUARES {
             commands.spawn((
                 Sprite {
-                    color: Srgba::hex(DARK_SQUARE_COLOR).unwrap().into(),
-                    custom_size: Some(Vec2::new(draw_info.square_size, draw_info.square_size)),
+                    color: Srgba::hex(theme.square_color(square)).unwrap().into(),
+                    custom_size: Some(Vec2::ONE),
                     ..default()
                 },
-                Transform::from_translation(Vec3::new(
-                    offset + (i as f32) * draw_info.square_size + (draw_info.square_size / 2.),
-                    offset + (j as f32) * draw_info.square_size + (draw_info.square_size / 2.),
-                    1.0,
-                )),
-                crate::Square,
+                square_transform(&draw_info, square, flipped),
+                crate::Square(square),
             ));
         }
+        return;
+    }
+    for (square, mut transform) in squares.iter_mut() {
+        *transform = square_transform(&draw_info, square.0, flipped);
+    }
+}
+
+/// Places a unit sized square sprite over `square`
+fn square_transform(draw_info: &DrawInfo, square: chess::Square, flipped: bool) -> Transform {
+    Transform::from_translation(square_center(draw_info, square, flipped).extend(1.0))
+        .with_scale(Vec3::new(draw_info.square_size, draw_info.square_size, 1.0))
+}
+
+pub fn piece_filename(piece: chess::Piece, color: chess::Color) -> &'static str {
+    match (piece, color) {
+        (chess::Piece::Pawn, chess::Color::White) => "white_pawn.svg",
+        (chess::Piece::Pawn, chess::Color::Black) => "black_pawn.svg",
+        (chess::Piece::Knight, chess::Color::White) => "white_knight.svg",
+        (chess::Piece::Knight, chess::Color::Black) => "black_knight.svg",
+        (chess::Piece::Bishop, chess::Color::White) => "white_bishop.svg",
+        (chess::Piece::Bishop, chess::Color::Black) => "black_bishop.svg",
+        (chess::Piece::Rook, chess::Color::White) => "white_rook.svg",
+        (chess::Piece::Rook, chess::Color::Black) => "black_rook.svg",
+        (chess::Piece::Queen, chess::Color::White) => "white_queen.svg",
+        (chess::Piece::Queen, chess::Color::Black) => "black_queen.svg",
+        (chess::Piece::King, chess::Color::White) => "white_king.svg",
+        (chess::Piece::King, chess::Color::Black) => "black_king.svg",
+    }
+}
+
+/// The squares each piece moved by `m` slides between, which is the rook as well
+/// as the king when castling
+///
+/// Drops don't slide.
+fn move_paths(before: &Position, m: chess::ChessMove) -> Vec<(chess::Square, chess::Square)> {
+    let (source, dest) = (m.get_source(), m.get_dest());
+    if source == dest {
+        return Vec::new();
+    }
+    let color = before.side_to_move();
+    let files = dest.get_file().to_index() as i32 - source.get_file().to_index() as i32;
+    let castling_rook = match before.piece_on(source) {
+        // Chess960 castling moves the king onto its own rook
+        Some(chess::Piece::King) if before.color_on(dest) == Some(color) => Some(dest),
+        Some(chess::Piece::King) if files.abs() == 2 => {
+            let corner = if files > 0 {
+                chess::File::H
+            } else {
+                chess::File::A
+            };
+            Some(chess::Square::make_square(color.to_my_backrank(), corner))
+        }
+        _ => None,
+    };
+    match castling_rook {
+        Some(rook) => vec![
+            (source, chess960::king_destination(color, m)),
+            (rook, chess960::rook_destination(color, m)),
+        ],
+        None => vec![(source, dest)],
     }
 }
 
+/// A piece sliding to its square after a move, removed once it arrives
+#[derive(Component)]
+pub struct Slide {
+    from: Vec2,
+    to: Vec2,
+    timer: Timer,
+}
+
 pub fn draw_pieces(
     mut commands: Commands,
     board: Res<crate::game::Board>,
-    side_to_move: Res<crate::SideToMove>,
-    switch_sides: Res<crate::SwitchSides>,
+    view: BoardView,
     draw_info: Res<DrawInfo>,
-    entities: Query<Entity, With<crate::Piece>>,
+    animation: Res<MoveAnimation>,
+    mut pieces: Query<(
+        Entity,
+        &mut crate::Piece,
+        &mut Svg2d,
+        &mut Transform,
+        Has<Slide>,
+    )>,
     asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
     mut up_ev: EventReader<TurnEndEvent>,
     mut window_ev: EventReader<WindowResized>,
+    mut last_node: Local<Option<NodeId>>,
 ) {
     if up_ev.is_empty() && window_ev.is_empty() {
         return;
@@ -135,57 +264,413 @@ pub fn draw_pieces(
     for _ in window_ev.read() {}
     debug!("Redrawing pieces...");
 
-    // Every piece is despawned and recreated when a move occurs
-    // This is because we have to synchronize the sprites with the `chess` representation
-    //     of the pieces.
-    // It's way easier this way
+    // only moves as they are played slide, not resizes or jumps through the game
+    let tree = board.tree();
+    let node = tree.current_node();
+    let previous = last_node.replace(tree.current());
+    let paths = match (node.parent, node.mv) {
+        (Some(parent), Some(m)) if previous == Some(parent) && animation.seconds > 0. => {
+            move_paths(&tree.node(parent).board, m)
+        }
+        _ => Vec::new(),
+    };
+
+    // the sprites already on the board are kept, and only the squares that changed
+    // get pieces spawned, swapped or despawned
+    let mut on_square: HashMap<chess::Square, Entity> = pieces
+        .iter()
+        .map(|(e, piece, ..)| (piece.square, e))
+        .collect();
+    let sliding: Vec<(Entity, chess::Square, chess::Square)> = paths
+        .iter()
+        .filter_map(|&(from, to)| on_square.remove(&from).map(|e| (e, from, to)))
+        .collect();
+    let mut slides = HashMap::new();
+    for (e, from, to) in sliding {
+        // captured pieces make way for the one sliding in
+        if let Some(captured) = on_square.insert(to, e) {
+            commands.entity(captured).despawn_recursive();
+        }
+        slides.insert(to, from);
+    }
+
+    let flipped = view.flipped();
+    let scale = Vec3::new(
+        draw_info.square_size / SPRITE_SIZE,
+        draw_info.square_size / SPRITE_SIZE,
+        1.0,
+    );
+    for square in chess::ALL_SQUARES {
+        let wanted = board.piece_on(square).zip(board.color_on(square));
+        let pos = square_center(&draw_info, square, flipped);
+        let from = slides
+            .get(&square)
+            .map(|&from| square_center(&draw_info, from, flipped));
+        let transform =
+            Transform::from_translation(from.unwrap_or(pos).extend(3.0)).with_scale(scale);
 
-    for entity in entities.iter() {
-        commands.entity(entity).despawn_recursive();
+        let (entity, was_sliding) = match (on_square.get(&square), wanted) {
+            (None, None) => continue,
+            (Some(&e), None) => {
+                commands.entity(e).despawn_recursive();
+                continue;
+            }
+            (None, Some((piece, color))) => (
+                commands
+                    .spawn((
+                        Svg2d(asset_server.load(theme.piece_path(piece, color))),
+                        Origin::Center,
+                        transform,
+                        crate::Piece {
+                            square,
+                            piece,
+                            color,
+                        },
+                    ))
+                    .id(),
+                false,
+            ),
+            (Some(&e), Some((piece, color))) => {
+                let (_, mut sprite, mut svg, mut old_transform, was_sliding) =
+                    pieces.get_mut(e).unwrap();
+                // promotions and jumps through the game change what stands on a square
+                if (sprite.piece, sprite.color) != (piece, color) {
+                    svg.0 = asset_server.load(theme.piece_path(piece, color));
+                }
+                *sprite = crate::Piece {
+                    square,
+                    piece,
+                    color,
+                };
+                *old_transform = transform;
+                (e, was_sliding)
+            }
+        };
+        // a slide still running from the last move would drag the piece back
+        if let Some(from) = from {
+            commands.entity(entity).try_insert(Slide {
+                from,
+                to: pos,
+                timer: Timer::from_seconds(animation.seconds, TimerMode::Once),
+            });
+        } else if was_sliding {
+            commands.entity(entity).remove::<Slide>();
+        }
     }
+}
 
-    let offset = -draw_info.square_size * BOARD_LENGTH as f32 / 2.;
-    let is_white = side_to_move.0 == chess::Color::White;
-    for square in board.combined().into_iter() {
-        let color = board.color_on(square).unwrap();
-        let piece = board.piece_on(square).unwrap();
-        let mut rank = square.get_rank().to_index() as f32;
-        let file = square.get_file().to_index() as f32;
-
-        if !is_white && switch_sides.0 {
-            rank = 7. - rank;
+pub fn animate_slides(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut slides: Query<(Entity, &mut Slide, &mut Transform)>,
+) {
+    for (entity, mut slide, mut transform) in slides.iter_mut() {
+        slide.timer.tick(time.delta());
+        let t = slide.timer.fraction();
+        let eased = t * t * (3. - 2. * t);
+        // moving pieces pass over the others
+        let z = if slide.timer.finished() { 3.0 } else { 3.5 };
+        transform.translation = slide.from.lerp(slide.to, eased).extend(z);
+        if slide.timer.finished() {
+            commands.entity(entity).remove::<Slide>();
+        }
+    }
+}
+
+/// The center of the pocket slot holding `piece`
+///
+/// Pockets sit left of the board, the pocket of the side at the bottom of the
+/// board in its lower half, with pawns closest to the edge of the board.
+pub fn pocket_position(
+    draw_info: &DrawInfo,
+    flipped: bool,
+    piece: chess::Piece,
+    color: chess::Color,
+) -> Vec2 {
+    let slot = draw_info.square_size * POCKET_SLOT_SCALE;
+    let half_board = draw_info.square_size * BOARD_LENGTH as f32 / 2.;
+    let row = (piece.to_index() as f32 + 0.5) * slot;
+    let at_bottom = (color == chess::Color::White) != flipped;
+    Vec2::new(
+        -half_board - draw_info.square_size * 0.1 - slot / 2.,
+        if at_bottom {
+            -half_board + row
+        } else {
+            half_board - row
+        },
+    )
+}
+
+/// The pocket slot under `pos`, whether or not it holds any pieces
+pub fn pocket_at(
+    draw_info: &DrawInfo,
+    flipped: bool,
+    pos: Vec2,
+) -> Option<(chess::Piece, chess::Color)> {
+    let half = draw_info.square_size * POCKET_SLOT_SCALE / 2.;
+    chess::ALL_COLORS
+        .iter()
+        .flat_map(|&color| {
+            crazyhouse::POCKET_PIECES
+                .iter()
+                .map(move |&piece| (piece, color))
+        })
+        .find(|&(piece, color)| {
+            let center = pocket_position(draw_info, flipped, piece, color);
+            (pos.x - center.x).abs() < half && (pos.y - center.y).abs() < half
+        })
+}
+
+pub fn draw_pockets(
+    mut commands: Commands,
+    board: Res<crate::game::Board>,
+    view: BoardView,
+    draw_info: Res<DrawInfo>,
+    mut entities: Query<(Entity, &crate::Pocket, &mut Transform, Option<&mut Sprite>)>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    mut up_ev: EventReader<TurnEndEvent>,
+    mut window_ev: EventReader<WindowResized>,
+) {
+    if up_ev.is_empty() && window_ev.is_empty() {
+        return;
+    }
+    for _ in up_ev.read() {}
+    for _ in window_ev.read() {}
+
+    let flipped = view.flipped();
+    let slot = draw_info.square_size * POCKET_SLOT_SCALE;
+    let panel_size = Vec2::new(slot * POCKET_WIDTH, slot * 5.);
+    let mut wanted = Vec::new();
+    if board.variant() == Variant::Crazyhouse {
+        for color in chess::ALL_COLORS {
+            let pawn = pocket_position(&draw_info, flipped, chess::Piece::Pawn, color);
+            let queen = pocket_position(&draw_info, flipped, chess::Piece::Queen, color);
+            wanted.push((
+                crate::Pocket::Panel(color),
+                Transform::from_translation(Vec3::new(
+                    pawn.x - slot * (POCKET_WIDTH - 1.) / 2.,
+                    (pawn.y + queen.y) / 2.,
+                    1.0,
+                )),
+            ));
+
+            for piece in crazyhouse::POCKET_PIECES {
+                let count = board.state().in_pocket(color, piece);
+                // additional copies fan out to the left, squeezed to fit the panel
+                let spread = if count > 1 {
+                    (0.25f32).min((POCKET_WIDTH - 1.) / (count - 1) as f32)
+                } else {
+                    0.
+                };
+                let pos = pocket_position(&draw_info, flipped, piece, color);
+                for index in 0..count {
+                    let i = index as f32;
+                    wanted.push((
+                        crate::Pocket::Piece {
+                            piece,
+                            color,
+                            index,
+                        },
+                        Transform::from_translation(Vec3::new(
+                            pos.x - i * spread * slot,
+                            pos.y,
+                            3.0 - i * 0.01,
+                        ))
+                        .with_scale(Vec3::new(
+                            slot / SPRITE_SIZE,
+                            slot / SPRITE_SIZE,
+                            1.0,
+                        )),
+                    ));
+                }
+            }
         }
+    }
 
-        let filename = match (piece, color) {
-            (chess::Piece::Pawn, chess::Color::White) => "white_pawn.svg",
-            (chess::Piece::Pawn, chess::Color::Black) => "black_pawn.svg",
-            (chess::Piece::Knight, chess::Color::White) => "white_knight.svg",
-            (chess::Piece::Knight, chess::Color::Black) => "black_knight.svg",
-            (chess::Piece::Bishop, chess::Color::White) => "white_bishop.svg",
-            (chess::Piece::Bishop, chess::Color::Black) => "black_bishop.svg",
-            (chess::Piece::Rook, chess::Color::White) => "white_rook.svg",
-            (chess::Piece::Rook, chess::Color::Black) => "black_rook.svg",
-            (chess::Piece::Queen, chess::Color::White) => "white_queen.svg",
-            (chess::Piece::Queen, chess::Color::Black) => "black_queen.svg",
-            (chess::Piece::King, chess::Color::White) => "white_king.svg",
-            (chess::Piece::King, chess::Color::Black) => "black_king.svg",
+    // the panels and pieces already drawn are moved into place, and only pieces
+    // entering or leaving a pocket get spawned or despawned
+    for (entity, pocket, mut transform, sprite) in entities.iter_mut() {
+        let Some(index) = wanted.iter().position(|(p, _)| p == pocket) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
         };
-        let svg = asset_server.load(filename);
-        commands.spawn((
-            Svg2d(svg.clone()),
-            Origin::Center,
-            Transform::from_translation(Vec3::new(
-                offset + file * draw_info.square_size + (draw_info.square_size / 2.),
-                offset + rank * draw_info.square_size + (draw_info.square_size / 2.),
-                3.0,
-            ))
-            .with_scale(Vec3::new(
-                draw_info.square_size / SPRITE_SIZE,
-                draw_info.square_size / SPRITE_SIZE,
-                1.0,
+        *transform = wanted.swap_remove(index).1;
+        if let Some(mut sprite) = sprite {
+            sprite.custom_size = Some(panel_size);
+        }
+    }
+    for (pocket, transform) in wanted {
+        match pocket {
+            crate::Pocket::Panel(_) => commands.spawn((
+                Sprite {
+                    color: Srgba::hex(theme.palette.pocket).unwrap().into(),
+                    custom_size: Some(panel_size),
+                    ..default()
+                },
+                transform,
+                pocket,
             )),
-            crate::Piece,
-        ));
+            crate::Pocket::Piece { piece, color, .. } => commands.spawn((
+                Svg2d(asset_server.load(theme.piece_path(piece, color))),
+                Origin::Center,
+                transform,
+                pocket,
+            )),
+        };
+    }
+}
+
+/// An Atomic explosion or a captured piece, despawned when its timer runs out
+#[derive(Component)]
+pub struct CaptureEffect {
+    timer: Timer,
+    scale: Vec3,
+    kind: EffectKind,
+}
+
+enum EffectKind {
+    /// Flares up and fades out
+    Explosion,
+    /// A captured piece, left in place while its cover fades in
+    Captured,
+    /// A sprite the color of the square, fading in over a captured piece to fade
+    /// it out, as the SVG pieces have no alpha of their own
+    Cover,
+}
+
+impl CaptureEffect {
+    fn new(kind: EffectKind, scale: Vec3, seconds: f32) -> Self {
+        Self {
+            timer: Timer::from_seconds(seconds, TimerMode::Once),
+            scale,
+            kind,
+        }
+    }
+}
+
+fn square_center(draw_info: &DrawInfo, square: chess::Square, flipped: bool) -> Vec2 {
+    let offset = -draw_info.square_size * BOARD_LENGTH as f32 / 2.;
+    let mut rank = square.get_rank().to_index() as f32;
+    let file = square.get_file().to_index() as f32;
+    if flipped {
+        rank = 7. - rank;
+    }
+    Vec2::new(
+        offset + file * draw_info.square_size + (draw_info.square_size / 2.),
+        offset + rank * draw_info.square_size + (draw_info.square_size / 2.),
+    )
+}
+
+pub fn spawn_capture_effects(
+    mut commands: Commands,
+    board: Res<crate::game::Board>,
+    view: BoardView,
+    draw_info: Res<DrawInfo>,
+    animation: Res<MoveAnimation>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    mut last_node: Local<Option<NodeId>>,
+) {
+    let tree = board.tree();
+    let current = tree.current();
+    let previous = last_node.replace(current);
+    if previous == Some(current) {
+        return;
+    }
+    let node = tree.current_node();
+    let (Some(parent), Some(m)) = (node.parent, node.mv) else {
+        return;
+    };
+    // only moves as they are played are animated, not jumps through the game
+    if previous != Some(parent) {
+        return;
+    }
+
+    let before = &tree.node(parent).board;
+    let flipped = view.flipped();
+    let square_size = draw_info.square_size;
+    match board.variant() {
+        Variant::Atomic => {
+            let Some(blast) = atomic::explosion(before, m) else {
+                return;
+            };
+            for square in blast {
+                let pos = square_center(&draw_info, square, flipped);
+                commands.spawn((
+                    Sprite {
+                        color: Srgba::hex(EXPLOSION_COLOR).unwrap().into(),
+                        custom_size: Some(Vec2::new(square_size, square_size)),
+                        ..default()
+                    },
+                    Transform::from_translation(pos.extend(4.0)),
+                    CaptureEffect::new(EffectKind::Explosion, Vec3::ONE, CAPTURE_EFFECT_SECONDS),
+                ));
+            }
+        }
+        // captured pieces fade out under the capturing piece as it slides in, and
+        // under the highlights of the move
+        _ => {
+            if !before.is_capture(m) || animation.seconds <= 0. {
+                return;
+            }
+            let square = match before.en_passant() {
+                Some(pawn) if before.is_en_passant(m) => pawn,
+                _ => m.get_dest(),
+            };
+            let (Some(piece), Some(color)) = (before.piece_on(square), before.color_on(square))
+            else {
+                return;
+            };
+            let pos = square_center(&draw_info, square, flipped);
+            let scale = Vec3::new(square_size / SPRITE_SIZE, square_size / SPRITE_SIZE, 1.0);
+            commands.spawn((
+                Svg2d(asset_server.load(theme.piece_path(piece, color))),
+                Origin::Center,
+                Transform::from_translation(pos.extend(1.2)).with_scale(scale),
+                CaptureEffect::new(EffectKind::Captured, scale, animation.seconds),
+            ));
+            commands.spawn((
+                Sprite {
+                    color: Srgba::hex(theme.square_color(square))
+                        .unwrap()
+                        .with_alpha(0.)
+                        .into(),
+                    custom_size: Some(Vec2::new(square_size, square_size)),
+                    ..default()
+                },
+                Transform::from_translation(pos.extend(1.3)),
+                CaptureEffect::new(EffectKind::Cover, Vec3::ONE, animation.seconds),
+            ));
+        }
+    }
+}
+
+pub fn animate_capture_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effects: Query<(
+        Entity,
+        &mut CaptureEffect,
+        &mut Transform,
+        Option<&mut Sprite>,
+    )>,
+) {
+    for (entity, mut effect, mut transform, sprite) in effects.iter_mut() {
+        effect.timer.tick(time.delta());
+        if effect.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        let t = effect.timer.fraction();
+        match (&effect.kind, sprite) {
+            (EffectKind::Explosion, Some(mut sprite)) => {
+                transform.scale = effect.scale * (1. + t / 2.);
+                sprite.color.set_alpha(1. - t);
+            }
+            (EffectKind::Cover, Some(mut sprite)) => sprite.color.set_alpha(t),
+            _ => {}
+        }
     }
 }
 
@@ -210,45 +695,339 @@ pub fn cursor_swap(
     }
 }
 
+/// Labels the files and ranks, following the orientation of the board
+///
+/// Bevy has no font to draw text in the world with, so the labels are painted
+/// by egui behind its panels.
+pub fn draw_coordinates(
+    mut contexts: EguiContexts,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    view: BoardView,
+    draw_info: Res<DrawInfo>,
+    coordinates: Res<Coordinates>,
+    theme: Res<Theme>,
+) {
+    if *coordinates == Coordinates::Off {
+        return;
+    }
+    let (camera, camera_transform) = camera.single();
+    let to_screen = |world: Vec2| screen_position(camera, camera_transform, world);
+    let flipped = view.flipped();
+    let square_size = draw_info.square_size;
+    let half_board = square_size * BOARD_LENGTH as f32 / 2.;
+    let inset = square_size / 2. - square_size * 0.05;
+    let font = egui::FontId::proportional(square_size * 0.22);
+    // inside the board the labels take the color of the other kind of square
+    let contrast = |square: chess::Square| {
+        let dark = (square.get_rank().to_index() + square.get_file().to_index()) % 2 == 0;
+        let color = if dark {
+            theme.palette.light_square
+        } else {
+            theme.palette.dark_square
+        };
+        egui::Color32::from_hex(color).unwrap()
+    };
+
+    let painter = contexts.ctx_mut().layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("coordinates"),
+    ));
+    let bottom = if flipped {
+        chess::Rank::Eighth
+    } else {
+        chess::Rank::First
+    };
+    for file in chess::ALL_FILES {
+        let square = chess::Square::make_square(bottom, file);
+        let center = square_center(&draw_info, square, flipped);
+        let (pos, anchor, color) = match *coordinates {
+            Coordinates::Outside => (
+                Vec2::new(center.x, -half_board - square_size * 0.05),
+                egui::Align2::CENTER_TOP,
+                egui::Color32::GRAY,
+            ),
+            _ => (
+                center + Vec2::new(inset, -inset),
+                egui::Align2::RIGHT_BOTTOM,
+                contrast(square),
+            ),
+        };
+        let label = ((b'a' + file.to_index() as u8) as char).to_string();
+        if let Some(pos) = to_screen(pos) {
+            painter.text(pos, anchor, label, font.clone(), color);
+        }
+    }
+    for rank in chess::ALL_RANKS {
+        let square = chess::Square::make_square(rank, chess::File::A);
+        let center = square_center(&draw_info, square, flipped);
+        let (pos, anchor, color) = match *coordinates {
+            Coordinates::Outside => (
+                Vec2::new(-half_board - square_size * 0.05, center.y),
+                egui::Align2::RIGHT_CENTER,
+                egui::Color32::GRAY,
+            ),
+            _ => (
+                center + Vec2::new(-inset, inset),
+                egui::Align2::LEFT_TOP,
+                contrast(square),
+            ),
+        };
+        let label = (rank.to_index() + 1).to_string();
+        if let Some(pos) = to_screen(pos) {
+            painter.text(pos, anchor, label, font.clone(), color);
+        }
+    }
+}
+
+/// Where a point in the world appears in egui's screen coordinates
+fn screen_position(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    world: Vec2,
+) -> Option<egui::Pos2> {
+    camera
+        .world_to_viewport(camera_transform, world.extend(0.))
+        .ok()
+        .map(|pos| egui::pos2(pos.x, pos.y))
+}
+
+/// Paints the arrows and circles drawn on the position over the pieces, along
+/// with the one being dragged out
+pub fn render_annotations(
+    mut contexts: EguiContexts,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    view: BoardView,
+    draw_info: Res<DrawInfo>,
+    annotations: Res<Annotations>,
+    pointed_square: Res<PointedSquare>,
+) {
+    let dragged = annotations
+        .drawing
+        .zip(**pointed_square)
+        .map(|((from, color), to)| Shape { from, to, color });
+    if annotations.shapes.is_empty() && dragged.is_none() {
+        return;
+    }
+    let (camera, camera_transform) = camera.single();
+    let flipped = view.flipped();
+    let square_size = draw_info.square_size;
+    let to_screen = |square: chess::Square| {
+        screen_position(
+            camera,
+            camera_transform,
+            square_center(&draw_info, square, flipped),
+        )
+    };
+
+    let painter = contexts.ctx_mut().layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("annotations"),
+    ));
+    for shape in annotations.shapes.iter().chain(dragged.iter()) {
+        let (Some(from), Some(to)) = (to_screen(shape.from), to_screen(shape.to)) else {
+            continue;
+        };
+        let color = egui::Color32::from_hex(shape.color.hex()).unwrap();
+        if shape.is_circle() {
+            let stroke = egui::Stroke::new(square_size * 0.07, color);
+            painter.circle_stroke(from, square_size * 0.45, stroke);
+            continue;
+        }
+        // the shaft stops where the head begins, so the overlap doesn't darken
+        let direction = (to - from).normalized();
+        let normal = direction.rot90();
+        let head_length = square_size * 0.4;
+        let head_base = to - direction * head_length;
+        let stroke = egui::Stroke::new(square_size * 0.15, color);
+        painter.line_segment([from, head_base], stroke);
+        painter.add(egui::Shape::convex_polygon(
+            vec![
+                to,
+                head_base + normal * square_size * 0.22,
+                head_base - normal * square_size * 0.22,
+            ],
+            color,
+            egui::Stroke::NONE,
+        ));
+    }
+}
+
+/// A red radial gradient drawn under a king in check
+#[derive(Resource)]
+pub struct CheckGlow(Handle<Image>);
+
+pub fn setup_check_glow(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let size = CHECK_GLOW_RESOLUTION;
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let center = (size as f32 - 1.) / 2.;
+            let distance = Vec2::new(x as f32 - center, y as f32 - center).length() / center;
+            // solid at the center, fading out before the edge of the square
+            let alpha = (1. - distance / 0.9).clamp(0., 1.);
+            data.extend_from_slice(&CHECK_COLOR);
+            data.push((alpha * 255.) as u8);
+        }
+    }
+    let image = Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    commands.insert_resource(CheckGlow(images.add(image)));
+}
+
+/// Highlights the squares of the last move and the king of the side to move
+/// when it is in check
+///
+/// The highlights are spawned once and shown, hidden and moved from then on.
+pub fn render_highlights(
+    mut commands: Commands,
+    board: Res<crate::game::Board>,
+    view: BoardView,
+    draw_info: Res<DrawInfo>,
+    check_glow: Res<CheckGlow>,
+    mut query: Query<(
+        &crate::Highlight,
+        &mut Sprite,
+        &mut Transform,
+        &mut Visibility,
+    )>,
+    mut up_ev: EventReader<TurnEndEvent>,
+    mut window_ev: EventReader<WindowResized>,
+) {
+    if up_ev.is_empty() && window_ev.is_empty() {
+        return;
+    }
+    for _ in up_ev.read() {}
+    for _ in window_ev.read() {}
+
+    let flipped = view.flipped();
+    let size = Some(Vec2::splat(draw_info.square_size));
+    let last_move = board.tree().current_node().mv;
+    let check = if board.in_check() {
+        board.king_square(board.side_to_move())
+    } else {
+        None
+    };
+    let placement = |highlight: crate::Highlight| {
+        let square = match highlight {
+            crate::Highlight::LastMoveTo => last_move.map(|m| m.get_dest()),
+            // drops only have a destination
+            crate::Highlight::LastMoveFrom => last_move
+                .filter(|m| m.get_source() != m.get_dest())
+                .map(|m| m.get_source()),
+            crate::Highlight::Check => check,
+        };
+        let z = match highlight {
+            crate::Highlight::Check => 1.6,
+            _ => 1.5,
+        };
+        match square {
+            Some(square) => (
+                Transform::from_translation(square_center(&draw_info, square, flipped).extend(z)),
+                Visibility::Inherited,
+            ),
+            None => (Transform::default(), Visibility::Hidden),
+        }
+    };
+
+    if query.is_empty() {
+        for highlight in [
+            crate::Highlight::LastMoveFrom,
+            crate::Highlight::LastMoveTo,
+            crate::Highlight::Check,
+        ] {
+            let sprite = match highlight {
+                crate::Highlight::Check => Sprite {
+                    image: check_glow.0.clone(),
+                    custom_size: size,
+                    ..default()
+                },
+                _ => Sprite {
+                    color: Srgba::hex(LAST_MOVE_COLOR).unwrap().into(),
+                    custom_size: size,
+                    ..default()
+                },
+            };
+            let (transform, visibility) = placement(highlight);
+            commands.spawn((sprite, transform, visibility, highlight));
+        }
+        return;
+    }
+    for (&highlight, mut sprite, mut transform, mut visibility) in query.iter_mut() {
+        sprite.custom_size = size;
+        (*transform, *visibility) = placement(highlight);
+    }
+}
+
+/// The Crazyhouse piece following the cursor while it is dragged out of the pocket
+#[derive(Component)]
+pub struct HeldPocketPiece;
+
+/// Highlights the selected piece and where it can go, redrawn only when the
+/// selection, the position or the window changes
 pub fn render_selector(
     mut commands: Commands,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    input: Res<ButtonInput<MouseButton>>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
     selected_piece: Res<SelectedPiece>,
-    side_to_move: Res<crate::SideToMove>,
-    switch_sides: Res<crate::SwitchSides>,
+    view: BoardView,
     draw_info: Res<DrawInfo>,
     query: Query<Entity, With<crate::Selector>>,
+    mut held: Query<(&mut Transform, &mut Visibility), With<HeldPocketPiece>>,
     board: Res<crate::game::Board>,
+    mut up_ev: EventReader<TurnEndEvent>,
+    mut window_ev: EventReader<WindowResized>,
 ) {
+    // the held piece is the only part that moves between redraws
+    for (mut transform, mut visibility) in held.iter_mut() {
+        match cursor_world_position(&camera, &window) {
+            Some(cursor) if input.pressed(MouseButton::Left) => {
+                transform.translation = cursor.extend(4.0);
+                *visibility = Visibility::Visible;
+            }
+            _ => *visibility = Visibility::Hidden,
+        }
+    }
+
+    if !selected_piece.is_changed() && up_ev.is_empty() && window_ev.is_empty() {
+        return;
+    }
+    for _ in up_ev.read() {}
+    for _ in window_ev.read() {}
+
     for e in query.iter() {
         commands.entity(e).despawn_recursive();
     }
 
-    let is_white = side_to_move.0 == chess::Color::White;
+    let flipped = view.flipped();
+    let square_size = draw_info.square_size;
+    let highlight = |commands: &mut Commands, square: chess::Square, color: &str| {
+        commands.spawn((
+            Sprite {
+                color: Srgba::hex(color).unwrap().into(),
+                custom_size: Some(Vec2::new(square_size, square_size)),
+                ..default()
+            },
+            Transform::from_translation(square_center(&draw_info, square, flipped).extend(2.0)),
+            crate::Selector,
+        ));
+    };
 
     match *selected_piece {
         SelectedPiece::None => {}
         SelectedPiece::Some { piece, square } => {
-            let mut rank = square.get_rank().to_index() as f32;
-            let file = square.get_file().to_index() as f32;
-
-            if !is_white && switch_sides.0 {
-                rank = 7. - rank;
-            }
-
-            let offset = -draw_info.square_size * BOARD_LENGTH as f32 / 2.;
-            commands.spawn((
-                Sprite {
-                    color: Srgba::hex(SELECT_COLOR).unwrap().into(),
-                    custom_size: Some(Vec2::new(draw_info.square_size, draw_info.square_size)),
-                    ..default()
-                },
-                Transform::from_translation(Vec3::new(
-                    offset + file * draw_info.square_size + (draw_info.square_size / 2.),
-                    offset + rank * draw_info.square_size + (draw_info.square_size / 2.),
-                    2.0,
-                )),
-                crate::Selector,
-            ));
+            highlight(&mut commands, square, theme.palette.select);
             let self_bitboard = board.color_combined(board.side_to_move());
             let moves = match piece {
                 chess::Piece::Pawn => {
@@ -265,26 +1044,46 @@ pub fn render_selector(
             } & !self_bitboard;
 
             for m in moves.into_iter() {
-                let mut rank = m.get_rank().to_index() as f32;
-                let file = m.get_file().to_index() as f32;
+                highlight(&mut commands, m, MOVE_HINT_COLOR);
+            }
+        }
+        SelectedPiece::Promotion { dest, .. } => {
+            highlight(&mut commands, dest, theme.palette.select);
+        }
+        SelectedPiece::Pocket { piece } => {
+            let slot = square_size * POCKET_SLOT_SCALE;
+            let pos = pocket_position(&draw_info, flipped, piece, board.side_to_move());
+            commands.spawn((
+                Sprite {
+                    color: Srgba::hex(theme.palette.select).unwrap().into(),
+                    custom_size: Some(Vec2::new(slot, slot)),
+                    ..default()
+                },
+                Transform::from_translation(pos.extend(2.0)),
+                crate::Selector,
+            ));
 
-                if !is_white && switch_sides.0 {
-                    rank = 7. - rank;
+            for m in board.legal_moves() {
+                match crazyhouse::as_drop(m) {
+                    Some((dropped, square)) if dropped == piece => {
+                        highlight(&mut commands, square, MOVE_HINT_COLOR);
+                    }
+                    _ => {}
                 }
-                commands.spawn((
-                    Sprite {
-                        color: Srgba::hex("#88888890").unwrap().into(),
-                        custom_size: Some(Vec2::new(draw_info.square_size, draw_info.square_size)),
-                        ..default()
-                    },
-                    Transform::from_translation(Vec3::new(
-                        offset + file * draw_info.square_size + (draw_info.square_size / 2.),
-                        offset + rank * draw_info.square_size + (draw_info.square_size / 2.),
-                        2.0,
-                    )),
-                    crate::Selector,
-                ));
             }
+
+            commands.spawn((
+                Svg2d(asset_server.load(theme.piece_path(piece, board.side_to_move()))),
+                Origin::Center,
+                Transform::from_scale(Vec3::new(
+                    square_size / SPRITE_SIZE,
+                    square_size / SPRITE_SIZE,
+                    1.0,
+                )),
+                Visibility::Hidden,
+                HeldPocketPiece,
+                crate::Selector,
+            ));
         }
     }
 }
@@ -0,0 +1,132 @@
+//! Watching a recorded game again, move by move
+//!
+//! The game is loaded as usual and reviewed from its start, so each step is
+//! animated and highlighted like a move played on the board. The live position is
+//! the end of the recording.
+
+use bevy::prelude::*;
+
+use crate::{
+    game::{
+        tree::{GameTree, NodeId},
+        Board, SelectedPiece,
+    },
+    SideToMove, TurnEndEvent,
+};
+
+/// How long each move is shown at normal speed
+const SECONDS_PER_MOVE: f32 = 1.0;
+
+#[derive(Resource, Debug)]
+pub struct Replay {
+    pub playing: bool,
+    /// A multiple of the normal speed
+    pub speed: f32,
+    /// Time the current move has been shown for, scaled by the speed
+    elapsed: f32,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self {
+            playing: false,
+            speed: 1.0,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Replay {
+    /// Shows the game on `board` from its start, paused
+    pub fn begin(&mut self, board: &mut Board) {
+        board.back_to_live();
+        board.review(GameTree::ROOT);
+        self.playing = false;
+        self.elapsed = 0.0;
+    }
+
+    /// Starts or stops playing, starting over if the end was reached
+    pub fn toggle(&mut self, board: &mut Board) {
+        if !self.playing && !board.is_reviewing() {
+            board.review(GameTree::ROOT);
+        }
+        self.playing = !self.playing;
+        self.elapsed = 0.0;
+    }
+}
+
+/// The positions of the recorded game, from the start to the end
+pub fn positions(board: &Board) -> Vec<NodeId> {
+    board.tree().path(board.live())
+}
+
+/// Steps through the game while it's playing
+pub fn autoplay(
+    time: Res<Time>,
+    mut replay: ResMut<Replay>,
+    mut board: ResMut<Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+) {
+    if !replay.playing {
+        return;
+    }
+    replay.elapsed += time.delta_secs() * replay.speed;
+    if replay.elapsed < SECONDS_PER_MOVE {
+        return;
+    }
+    replay.elapsed = 0.0;
+    board.review_forward();
+    if !board.is_reviewing() {
+        replay.playing = false;
+    }
+    side_to_move.0 = board.side_to_move();
+    *selected_piece = SelectedPiece::None;
+    up_ev.send(TurnEndEvent);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::parse_uci;
+
+    fn game() -> Board {
+        let mut board = Board::default();
+        for uci in ["e2e4", "e7e5", "g1f3"] {
+            board.play(parse_uci(uci).unwrap());
+        }
+        board
+    }
+
+    #[test]
+    fn replays_begin_at_the_start_of_the_game() {
+        let mut board = game();
+        let end = board.tree().current();
+        let mut replay = Replay::default();
+        replay.begin(&mut board);
+
+        assert!(!replay.playing);
+        assert_eq!(board.tree().current(), GameTree::ROOT);
+        assert_eq!(board.live(), end);
+        assert_eq!(positions(&board).len(), 4);
+    }
+
+    #[test]
+    fn playing_after_the_end_starts_over() {
+        let mut board = game();
+        let mut replay = Replay::default();
+        replay.begin(&mut board);
+        for _ in 0..3 {
+            board.review_forward();
+        }
+        assert!(!board.is_reviewing());
+
+        replay.toggle(&mut board);
+        assert!(replay.playing);
+        assert_eq!(board.tree().current(), GameTree::ROOT);
+
+        replay.toggle(&mut board);
+        assert!(!replay.playing);
+    }
+}
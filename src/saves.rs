@@ -0,0 +1,371 @@
+//! Games saved in named slots through [`crate::storage`]
+//!
+//! The game being played is also autosaved after every move to its own slot,
+//! which the main menu offers to continue until the game ends.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use miniserde::{Deserialize, Serialize};
+
+use crate::{
+    ai::{self, Engine},
+    game::{
+        tree::{fen_fullmove_number, fen_halfmove_clock, GameTree, NodeId},
+        Board,
+    },
+    storage,
+    variant::Variant,
+    FlipBoard, GameMode, GameRule, Last50, SwitchSides,
+};
+
+pub const AUTOSAVE: &str = "Autosave";
+const SAVES_DIR: &str = "saves";
+
+#[derive(Serialize, Deserialize)]
+struct SavedMove {
+    /// The position the move was played from, as one past its index in
+    /// `SavedGame::moves`, or zero for the start position
+    parent: usize,
+    uci: String,
+    comment: String,
+    nags: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTag {
+    name: String,
+    value: String,
+}
+
+/// A game with every variation and annotation, along with how it was being played
+#[derive(Serialize, Deserialize)]
+pub struct SavedGame {
+    variant: String,
+    /// The start position, unless it's one of the Chess960 start positions
+    fen: String,
+    chess960_position: Option<u16>,
+    tags: Vec<SavedTag>,
+    comment: String,
+    /// Every move in the tree, each after the one it follows, with variations in order
+    moves: Vec<SavedMove>,
+    /// The live position, numbered like `SavedMove::parent`
+    current: usize,
+    mode: String,
+    ai_plays_white: bool,
+    uci_engine: Option<String>,
+    switch_sides: bool,
+    flip_board: bool,
+}
+
+impl SavedGame {
+    /// Returns None for puzzles and replays, which aren't saved
+    pub fn new(
+        board: &Board,
+        mode: &GameMode,
+        game_rule: &GameRule,
+        switch_sides: &SwitchSides,
+        flip_board: &FlipBoard,
+    ) -> Option<SavedGame> {
+        let tree = board.tree();
+        let chess960_position = match tree.variant() {
+            Variant::Chess960 => Some(
+                (0..960).find(|&n| Variant::Chess960.start_position(n).0 == *tree.start_board())?,
+            ),
+            _ => None,
+        };
+
+        let mut numbers: HashMap<NodeId, usize> = HashMap::from([(GameTree::ROOT, 0)]);
+        let mut moves = Vec::new();
+        let mut stack: Vec<NodeId> = tree.node(GameTree::ROOT).children.clone();
+        stack.reverse();
+        while let Some(id) = stack.pop() {
+            let node = tree.node(id);
+            moves.push(SavedMove {
+                parent: numbers[&node.parent?],
+                uci: node.mv?.to_string(),
+                comment: node.comment.clone(),
+                nags: node.nags.clone(),
+            });
+            numbers.insert(id, moves.len());
+            stack.extend(node.children.iter().rev());
+        }
+
+        Some(SavedGame {
+            variant: tree.variant().name().to_string(),
+            fen: tree.fen(GameTree::ROOT, false),
+            chess960_position,
+            tags: tree
+                .tags()
+                .iter()
+                .map(|(name, value)| SavedTag {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            comment: tree.node(GameTree::ROOT).comment.clone(),
+            moves,
+            current: numbers[&board.live()],
+            mode: mode_name(mode)?.to_string(),
+            ai_plays_white: game_rule.ai_color == chess::Color::White,
+            uci_engine: match &game_rule.engine {
+                Engine::StockfishOnline => None,
+                Engine::Uci(path) => Some(path.clone()),
+            },
+            switch_sides: switch_sides.0,
+            flip_board: flip_board.0,
+        })
+    }
+
+    /// The game as it was saved, at its live position
+    ///
+    /// Returns None if the save can't be read back, such as when it holds an
+    /// illegal move.
+    pub fn tree(&self) -> Option<GameTree> {
+        let variant = Variant::ALL
+            .into_iter()
+            .find(|v| v.name() == self.variant)?;
+        let (start, state) = match self.chess960_position {
+            Some(n) => variant.start_position(n),
+            None => variant.parse_fen(&self.fen).ok()?,
+        };
+
+        let mut tree = GameTree::with_variant(variant, start, state);
+        tree.set_halfmove_clock(fen_halfmove_clock(&self.fen));
+        tree.set_fullmove_number(fen_fullmove_number(&self.fen));
+        tree.current_node_mut().comment = self.comment.clone();
+        for tag in self.tags.iter() {
+            tree.set_tag(&tag.name, tag.value.clone());
+        }
+        let mut ids = vec![GameTree::ROOT];
+        for saved in self.moves.iter() {
+            let m = ai::parse_uci(&saved.uci)?;
+            tree.go_to(*ids.get(saved.parent)?);
+            let node = tree.current_node();
+            if !variant
+                .rules()
+                .legal_moves(&node.board, &node.state)
+                .contains(&m)
+            {
+                return None;
+            }
+            tree.play(m);
+            let node = tree.current_node_mut();
+            node.comment = saved.comment.clone();
+            node.nags = saved.nags.clone();
+            ids.push(tree.current());
+        }
+        tree.go_to(*ids.get(self.current)?);
+        Some(tree)
+    }
+
+    /// Sets the game up again, returning the mode it was played in
+    ///
+    /// Nothing is changed if the save can't be read back.
+    pub fn restore(
+        &self,
+        board: &mut Board,
+        last_50: &mut Last50,
+        game_rule: &mut GameRule,
+        switch_sides: &mut SwitchSides,
+        flip_board: &mut FlipBoard,
+    ) -> Option<GameMode> {
+        let mode = mode_from_name(&self.mode)?;
+        let tree = self.tree()?;
+
+        board.load(tree);
+        *last_50 = Last50::following(board);
+        game_rule.ai_color = if self.ai_plays_white {
+            chess::Color::White
+        } else {
+            chess::Color::Black
+        };
+        game_rule.engine = match &self.uci_engine {
+            Some(path) if cfg!(not(target_arch = "wasm32")) => Engine::Uci(path.clone()),
+            _ => Engine::StockfishOnline,
+        };
+        switch_sides.0 = self.switch_sides;
+        flip_board.0 = self.flip_board;
+        Some(mode)
+    }
+
+    pub fn save(&self, name: &str) -> Result<(), String> {
+        storage::write(&key(name), &miniserde::json::to_string(self))
+    }
+
+    pub fn load(name: &str) -> Option<SavedGame> {
+        miniserde::json::from_str(&storage::read(&key(name))?).ok()
+    }
+}
+
+fn mode_name(mode: &GameMode) -> Option<&'static str> {
+    match mode {
+        GameMode::Hotseat => Some("Hotseat"),
+        GameMode::VsAi => Some("VsAi"),
+        GameMode::Sim => Some("Sim"),
+        GameMode::Analysis => Some("Analysis"),
+        GameMode::Puzzle | GameMode::Replay => None,
+    }
+}
+
+fn mode_from_name(name: &str) -> Option<GameMode> {
+    match name {
+        "Hotseat" => Some(GameMode::Hotseat),
+        "VsAi" => Some(GameMode::VsAi),
+        "Sim" => Some(GameMode::Sim),
+        "Analysis" => Some(GameMode::Analysis),
+        _ => None,
+    }
+}
+
+/// The storage key of a slot, with anything but letters, digits, spaces, dashes
+/// and underscores in its name replaced so it's safe as a file name
+fn key(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{SAVES_DIR}/{name}")
+}
+
+pub fn delete(name: &str) {
+    storage::remove(&key(name));
+}
+
+/// The saved games offered in the menus
+#[derive(Resource, Default)]
+pub struct SaveSlots {
+    /// The named slots, without the autosave
+    pub names: Vec<String>,
+    pub autosave: bool,
+    /// The name typed in for the next save
+    pub name: String,
+    pub error: Option<String>,
+}
+
+impl SaveSlots {
+    pub fn refresh(&mut self) {
+        let mut names = stored_names();
+        self.autosave = names.iter().any(|name| name == AUTOSAVE);
+        names.retain(|name| name != AUTOSAVE);
+        self.names = names;
+    }
+}
+
+/// The names of every save in order, the autosave included
+fn stored_names() -> Vec<String> {
+    let mut names: Vec<String> = storage::keys(SAVES_DIR)
+        .into_iter()
+        .filter_map(|key| Some(key.strip_prefix(SAVES_DIR)?[1..].to_string()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// The names of the games saved in named slots, in order
+pub fn names() -> Vec<String> {
+    let mut names = stored_names();
+    names.retain(|name| name != AUTOSAVE);
+    names
+}
+
+pub fn refresh_slots(mut slots: ResMut<SaveSlots>) {
+    slots.refresh();
+}
+
+/// Saves the game in progress whenever it changes
+pub fn autosave(
+    board: Res<Board>,
+    mode: Res<State<GameMode>>,
+    game_rule: Res<GameRule>,
+    switch_sides: Res<SwitchSides>,
+    flip_board: Res<FlipBoard>,
+) {
+    // a new game only replaces the autosave once a move has been made in it
+    if !board.is_changed() || board.tree().node(GameTree::ROOT).children.is_empty() {
+        return;
+    }
+    let Some(saved) = SavedGame::new(&board, &mode, &game_rule, &switch_sides, &flip_board) else {
+        return;
+    };
+    if let Err(e) = saved.save(AUTOSAVE) {
+        warn!("Couldn't autosave the game: {e}");
+    }
+}
+
+/// Finished games aren't offered to be continued
+pub fn clear_autosave() {
+    delete(AUTOSAVE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Saves `board` and restores it through JSON, as a save slot would
+    fn round_trip(board: &Board) -> Option<Board> {
+        let saved = SavedGame::new(
+            board,
+            &GameMode::Analysis,
+            &GameRule::default(),
+            &SwitchSides(false),
+            &FlipBoard(false),
+        )?;
+        let saved: SavedGame =
+            miniserde::json::from_str(&miniserde::json::to_string(&saved)).ok()?;
+        let mut restored = Board::default();
+        let mode = saved.restore(
+            &mut restored,
+            &mut Last50::default(),
+            &mut GameRule::default(),
+            &mut SwitchSides(false),
+            &mut FlipBoard(false),
+        )?;
+        assert_eq!(mode, GameMode::Analysis);
+        Some(restored)
+    }
+
+    fn play(board: &mut Board, moves: &[&str]) {
+        for uci in moves {
+            board.play(ai::parse_uci(uci).unwrap());
+        }
+    }
+
+    #[test]
+    fn variations_and_comments_are_restored() {
+        let mut board = Board::default();
+        play(&mut board, &["e2e4", "e7e5", "g1f3"]);
+        board.tree_mut().current_node_mut().comment = String::from("developing");
+        board.tree_mut().go_to(1);
+        play(&mut board, &["c7c5"]);
+        board.tree_mut().set_tag("White", "Someone");
+
+        let restored = round_trip(&board).unwrap();
+        let tree = restored.tree();
+        assert_eq!(tree.moves(), board.tree().moves());
+        assert_eq!(tree.node(tree.main_line_end()).comment, "developing");
+        assert_eq!(tree.node(1).children.len(), 2);
+        assert_eq!(tree.tag("White"), Some("Someone"));
+    }
+
+    #[test]
+    fn games_missing_a_king_are_restored() {
+        let (start, state) = Variant::Antichess
+            .parse_fen("8/8/8/3p4/8/8/4P3/8 w - - 0 1")
+            .unwrap();
+        let mut board = Board::default();
+        board.load(GameTree::with_variant(Variant::Antichess, start, state));
+        play(&mut board, &["e2e4", "d5e4"]);
+
+        let restored = round_trip(&board).unwrap();
+        assert_eq!(restored.variant(), Variant::Antichess);
+        assert_eq!(restored.tree().start_board(), &start);
+        assert_eq!(restored.tree().moves(), board.tree().moves());
+    }
+}
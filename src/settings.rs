@@ -0,0 +1,190 @@
+//! Preferences kept between launches
+//!
+//! The settings are stored as JSON through [`crate::storage`]. They are read once
+//! at startup to build the resources they describe, then collected from those
+//! resources again whenever one of them changes.
+
+use bevy::prelude::*;
+use miniserde::{Deserialize, Serialize};
+
+use crate::{
+    ai::Engine,
+    audio::AudioSettings,
+    game::handicap::Handicap,
+    render::{Coordinates, MoveAnimation},
+    storage,
+    theme::{Palette, Theme, PALETTES},
+    variant::Variant,
+    GameRule,
+};
+
+const STORAGE_KEY: &str = "settings";
+
+/// Enums are stored by the names shown in the menus, so unknown names from other
+/// versions fall back to the defaults
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Turn the board towards the side to move in hotseat games
+    pub auto_rotate: bool,
+    pub ai_plays_white: bool,
+    /// The path to a local UCI engine, or `None` for Stockfish Online
+    pub uci_engine: Option<String>,
+    pub variant: String,
+    pub chess960_position: u16,
+    pub handicap: String,
+    pub palette: String,
+    pub piece_set: String,
+    pub coordinates: String,
+    pub animation_seconds: f32,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings::collect(
+            &GameRule::default(),
+            &Theme::default(),
+            &Coordinates::default(),
+            &MoveAnimation::default(),
+            &AudioSettings::default(),
+        )
+    }
+}
+
+impl Settings {
+    /// Reads the saved settings, using the defaults if there are none or they can't
+    /// be read
+    pub fn load() -> Settings {
+        match storage::read(STORAGE_KEY).map(|text| miniserde::json::from_str(&text)) {
+            Some(Ok(settings)) => settings,
+            Some(Err(_)) => {
+                warn!("Couldn't read the saved settings, using the defaults");
+                Settings::default()
+            }
+            None => Settings::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = storage::write(STORAGE_KEY, &miniserde::json::to_string(self)) {
+            warn!("Couldn't save the settings: {e}");
+        }
+    }
+
+    /// The settings as they are in the resources they describe
+    pub fn collect(
+        game_rule: &GameRule,
+        theme: &Theme,
+        coordinates: &Coordinates,
+        animation: &MoveAnimation,
+        audio: &AudioSettings,
+    ) -> Settings {
+        Settings {
+            auto_rotate: game_rule.auto_rotate,
+            ai_plays_white: game_rule.ai_color == chess::Color::White,
+            uci_engine: match &game_rule.engine {
+                Engine::StockfishOnline => None,
+                Engine::Uci(path) => Some(path.clone()),
+            },
+            variant: game_rule.variant.name().to_string(),
+            chess960_position: game_rule.chess960_position,
+            handicap: game_rule.handicap.name().to_string(),
+            palette: theme.palette.name.to_string(),
+            piece_set: theme.piece_set.clone(),
+            coordinates: coordinates.name().to_string(),
+            animation_seconds: animation.seconds,
+            volume: audio.volume,
+            muted: audio.muted,
+        }
+    }
+
+    pub fn game_rule(&self) -> GameRule {
+        let default = GameRule::default();
+        GameRule {
+            auto_rotate: self.auto_rotate,
+            ai_color: if self.ai_plays_white {
+                chess::Color::White
+            } else {
+                chess::Color::Black
+            },
+            engine: match &self.uci_engine {
+                // local engines can't be run on the web
+                Some(path) if cfg!(not(target_arch = "wasm32")) => Engine::Uci(path.clone()),
+                _ => Engine::StockfishOnline,
+            },
+            variant: Variant::ALL
+                .into_iter()
+                .find(|v| v.name() == self.variant)
+                .unwrap_or(default.variant),
+            chess960_position: self.chess960_position.min(959),
+            handicap: Handicap::ALL
+                .into_iter()
+                .find(|h| h.name() == self.handicap)
+                .unwrap_or(default.handicap),
+        }
+    }
+
+    pub fn palette(&self) -> &'static Palette {
+        PALETTES
+            .iter()
+            .find(|p| p.name == self.palette)
+            .unwrap_or(&PALETTES[0])
+    }
+
+    pub fn theme(&self) -> Theme {
+        let mut theme = Theme {
+            palette: self.palette(),
+            ..default()
+        };
+        // the set may have been removed from the assets since
+        if theme.piece_sets.contains(&self.piece_set) {
+            theme.piece_set = self.piece_set.clone();
+        }
+        theme
+    }
+
+    pub fn coordinates(&self) -> Coordinates {
+        Coordinates::ALL
+            .into_iter()
+            .find(|c| c.name() == self.coordinates)
+            .unwrap_or_default()
+    }
+
+    pub fn animation(&self) -> MoveAnimation {
+        MoveAnimation {
+            seconds: self.animation_seconds.clamp(0., 1.),
+        }
+    }
+
+    pub fn audio(&self) -> AudioSettings {
+        AudioSettings {
+            volume: self.volume.clamp(0., 1.),
+            muted: self.muted,
+        }
+    }
+}
+
+/// Saves the settings whenever one of the resources they describe changes
+pub fn save_settings(
+    mut settings: ResMut<Settings>,
+    game_rule: Res<GameRule>,
+    theme: Res<Theme>,
+    coordinates: Res<Coordinates>,
+    animation: Res<MoveAnimation>,
+    audio: Res<AudioSettings>,
+) {
+    let changed = game_rule.is_changed()
+        || theme.is_changed()
+        || coordinates.is_changed()
+        || animation.is_changed()
+        || audio.is_changed();
+    if !changed {
+        return;
+    }
+    let current = Settings::collect(&game_rule, &theme, &coordinates, &animation, &audio);
+    if current != *settings {
+        current.save();
+        *settings = current;
+    }
+}
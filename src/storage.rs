@@ -0,0 +1,106 @@
+//! Small text records kept between launches
+//!
+//! On native each record is a JSON file in the `shake-chess` directory of the
+//! platform's config directory, with `/` in keys making subdirectories. On the web
+//! records are kept in `localStorage` under the same keys, prefixed by `shake-chess/`.
+//! Larger data is kept in directories next to the records, which only exist on native.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::path::PathBuf;
+
+    fn root() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("shake-chess"))
+    }
+
+    fn path(key: &str) -> Option<PathBuf> {
+        root().map(|root| root.join(format!("{key}.json")))
+    }
+
+    pub fn read(key: &str) -> Option<String> {
+        std::fs::read_to_string(path(key)?).ok()
+    }
+
+    /// A directory for data too large to keep in records, created if it's missing
+    pub fn directory(name: &str) -> Result<PathBuf, String> {
+        let dir = root().ok_or("no config directory")?.join(name);
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        Ok(dir)
+    }
+
+    pub fn write(key: &str, text: &str) -> Result<(), String> {
+        let path = path(key).ok_or("no config directory")?;
+        path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, text))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn remove(key: &str) {
+        if let Some(path) = path(key) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// The keys of the records directly in `dir`
+    pub fn keys(dir: &str) -> Vec<String> {
+        let Some(entries) = root().and_then(|root| std::fs::read_dir(root.join(dir)).ok()) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter_map(|name| Some(format!("{dir}/{}", name.strip_suffix(".json")?)))
+            .collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    const PREFIX: &str = "shake-chess/";
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    pub fn read(key: &str) -> Option<String> {
+        local_storage()?.get_item(&format!("{PREFIX}{key}")).ok()?
+    }
+
+    pub fn write(key: &str, text: &str) -> Result<(), String> {
+        local_storage()
+            .ok_or("no local storage")?
+            .set_item(&format!("{PREFIX}{key}"), text)
+            .map_err(|_| String::from("local storage is full"))
+    }
+
+    /// Browsers have no file system to keep larger data in
+    pub fn directory(_name: &str) -> Result<std::path::PathBuf, String> {
+        Err(String::from("not available on the web"))
+    }
+
+    pub fn remove(key: &str) {
+        if let Some(storage) = local_storage() {
+            let _ = storage.remove_item(&format!("{PREFIX}{key}"));
+        }
+    }
+
+    /// The keys of the records directly in `dir`
+    pub fn keys(dir: &str) -> Vec<String> {
+        let Some(storage) = local_storage() else {
+            return Vec::new();
+        };
+        let prefix = format!("{PREFIX}{dir}/");
+        (0..storage.length().unwrap_or(0))
+            .filter_map(|i| storage.key(i).ok()?)
+            .filter_map(|key| {
+                let name = key.strip_prefix(&prefix)?;
+                (!name.contains('/')).then(|| format!("{dir}/{name}"))
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+#[cfg(target_arch = "wasm32")]
+pub use web::*;
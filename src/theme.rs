@@ -0,0 +1,172 @@
+//! Board color palettes and piece sets
+//!
+//! A piece set is a directory under `assets/pieces/` holding the twelve pieces as
+//! `white_pawn.svg`, `black_king.svg` and so on. Any complete directory found
+//! there at startup can be picked in the settings.
+
+use bevy::prelude::*;
+use bevy_svg::prelude::*;
+
+use crate::{render::piece_filename, TurnEndEvent};
+
+pub const DEFAULT_PIECE_SET: &str = "classic";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub light_square: &'static str,
+    pub dark_square: &'static str,
+    pub select: &'static str,
+    pub background: &'static str,
+    pub pocket: &'static str,
+}
+
+pub static PALETTES: [Palette; 5] = [
+    Palette {
+        name: "Brown",
+        light_square: "#F0D9B5", // stolen from lichess
+        dark_square: "#B58863",  // ... again
+        select: "#ADD8E6",
+        background: "#313338", // stolen from discord
+        pocket: "#2B2D31",     // ... also discord
+    },
+    Palette {
+        name: "Blue",
+        light_square: "#DEE3E6",
+        dark_square: "#8CA2AD",
+        select: "#CDD26A",
+        background: "#2A3038",
+        pocket: "#22272E",
+    },
+    Palette {
+        name: "Green",
+        light_square: "#EEEED2",
+        dark_square: "#769656",
+        select: "#F6F669",
+        background: "#302E2B",
+        pocket: "#262421",
+    },
+    Palette {
+        name: "Gray",
+        light_square: "#DCDCDC",
+        dark_square: "#ABABAB",
+        select: "#ADD8E6",
+        background: "#2B2B2B",
+        pocket: "#222222",
+    },
+    Palette {
+        name: "Purple",
+        light_square: "#EFEFEF",
+        dark_square: "#8877B7",
+        select: "#F0C2E0",
+        background: "#2E2A36",
+        pocket: "#25222C",
+    },
+];
+
+#[derive(Resource)]
+pub struct Theme {
+    pub palette: &'static Palette,
+    pub piece_set: String,
+    /// The piece sets found in `assets/pieces/`
+    pub piece_sets: Vec<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            palette: &PALETTES[0],
+            piece_set: String::from(DEFAULT_PIECE_SET),
+            piece_sets: find_piece_sets(),
+        }
+    }
+}
+
+impl Theme {
+    /// The asset path of the piece's image in the chosen set
+    pub fn piece_path(&self, piece: chess::Piece, color: chess::Color) -> String {
+        format!("pieces/{}/{}", self.piece_set, piece_filename(piece, color))
+    }
+
+    pub fn square_color(&self, square: chess::Square) -> &'static str {
+        let dark = (square.get_rank().to_index() + square.get_file().to_index()) % 2 == 0;
+        if dark {
+            self.palette.dark_square
+        } else {
+            self.palette.light_square
+        }
+    }
+}
+
+/// Lists the directories in `assets/pieces/` that have an image for every piece
+#[cfg(not(target_arch = "wasm32"))]
+fn find_piece_sets() -> Vec<String> {
+    let dir = bevy::asset::io::file::FileAssetReader::get_base_path().join("assets/pieces");
+    let complete = |path: &std::path::Path| {
+        chess::ALL_COLORS.iter().all(|&color| {
+            chess::ALL_PIECES
+                .iter()
+                .all(|&piece| path.join(piece_filename(piece, color)).is_file())
+        })
+    };
+    let mut sets: Vec<String> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| complete(&entry.path()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(e) => {
+            warn!("Couldn't list the piece sets in {}: {e}", dir.display());
+            Vec::new()
+        }
+    };
+    if sets.is_empty() {
+        sets.push(String::from(DEFAULT_PIECE_SET));
+    }
+    sets.sort();
+    sets
+}
+
+/// Assets are fetched over HTTP on the web, where directories can't be listed
+#[cfg(target_arch = "wasm32")]
+fn find_piece_sets() -> Vec<String> {
+    vec![String::from(DEFAULT_PIECE_SET)]
+}
+
+/// Recolors the board and swaps the piece images whenever the theme changes
+pub fn apply_theme(
+    theme: Res<Theme>,
+    asset_server: Res<AssetServer>,
+    mut clear_color: ResMut<ClearColor>,
+    mut squares: Query<(&crate::Square, &mut Sprite)>,
+    mut pieces: Query<(&crate::Piece, &mut Svg2d)>,
+    mut pockets: Query<
+        (&crate::Pocket, Option<&mut Sprite>, Option<&mut Svg2d>),
+        (Without<crate::Square>, Without<crate::Piece>),
+    >,
+    mut up_ev: EventWriter<TurnEndEvent>,
+) {
+    if !theme.is_changed() {
+        return;
+    }
+    clear_color.0 = Srgba::hex(theme.palette.background).unwrap().into();
+    for (square, mut sprite) in squares.iter_mut() {
+        sprite.color = Srgba::hex(theme.square_color(square.0)).unwrap().into();
+    }
+    for (piece, mut svg) in pieces.iter_mut() {
+        svg.0 = asset_server.load(theme.piece_path(piece.piece, piece.color));
+    }
+    for (pocket, sprite, svg) in pockets.iter_mut() {
+        match (pocket, sprite, svg) {
+            (crate::Pocket::Panel(_), Some(mut sprite), _) => {
+                sprite.color = Srgba::hex(theme.palette.pocket).unwrap().into();
+            }
+            (crate::Pocket::Piece { piece, color, .. }, _, Some(mut svg)) => {
+                svg.0 = asset_server.load(theme.piece_path(*piece, *color));
+            }
+            _ => {}
+        }
+    }
+    // the selection is redrawn with the new colors on the next redraw
+    up_ev.send(TurnEndEvent);
+}
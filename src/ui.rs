@@ -1,38 +1,182 @@
+use std::str::FromStr;
+
 use crate::{
-    GameMode, GameResult, GameRule, GameState, SideToMove, SwitchSides, TurnEndEvent
+    ai::{self, Engine, Evaluation},
+    audio::AudioSettings,
+    editor::Editor,
+    explorer::Explorer,
+    game::{
+        handicap::Handicap,
+        material,
+        tree::{fen_fullmove_number, fen_halfmove_clock, GameTree, NodeId},
+        Ending, SelectedPiece,
+    },
+    library::{self, Column, Library},
+    openings::{self, CurrentOpening},
+    puzzle::{PuzzleSession, PuzzleStatus},
+    render::{Coordinates, MoveAnimation},
+    replay::{self, Replay},
+    saves::{self, SaveSlots, SavedGame},
+    theme::{Theme, PALETTES},
+    variant::{three_check::CHECKS_TO_WIN, Variant},
+    FlipBoard, GameMode, GameResult, GameRule, GameState, SideToMove, SwitchSides, TurnEndEvent,
 };
 use bevy::prelude::*;
 use bevy_egui::{
     egui::{self, FontId, RichText},
     EguiContexts,
 };
+use rand::Rng;
+
+/// Whether the settings window is open
+#[derive(Resource, Default)]
+pub struct ShowSettings(pub bool);
 
 pub fn main_menu(
     mut contexts: EguiContexts,
     mut state: ResMut<NextState<GameState>>,
     mut game_mode: ResMut<NextState<GameMode>>,
     mut switch_sides: ResMut<SwitchSides>,
+    mut flip_board: ResMut<FlipBoard>,
     mut up_ev: EventWriter<TurnEndEvent>,
-    _game_rule: ResMut<GameRule>,
+    mut game_rule: ResMut<GameRule>,
+    mut board: ResMut<crate::game::Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut puzzles: ResMut<PuzzleSession>,
+    mut show_settings: ResMut<ShowSettings>,
+    mut slots: ResMut<SaveSlots>,
+    mut last_50: ResMut<crate::Last50>,
 ) {
+    let mut load = None;
     let ctx = contexts.ctx_mut();
     egui::CentralPanel::default().show(ctx, |ui| {
         ui.vertical_centered(|ui| {
             ui.add_space(301.0 - 100.0);
             ui.heading(RichText::new("Shake Chess").font(FontId::proportional(40.0)));
 
+            let continue_button = egui::Button::new(
+                RichText::new("Continue Last Game").font(FontId::proportional(30.0)),
+            );
+            if ui.add_enabled(slots.autosave, continue_button).clicked() {
+                load = Some(String::from(saves::AUTOSAVE));
+            }
             let hotseat_button =
                 ui.button(RichText::new("Play Hotseat").font(FontId::proportional(30.0)));
             let vs_ai_button =
                 ui.button(RichText::new("Play vs. AI").font(FontId::proportional(30.0)));
             let ai_vs_ai_button =
                 ui.button(RichText::new("Watch AI vs. AI").font(FontId::proportional(30.0)));
+            let analysis_button =
+                ui.button(RichText::new("Analysis Board").font(FontId::proportional(30.0)));
+            let puzzle_button =
+                ui.button(RichText::new("Puzzles").font(FontId::proportional(30.0)));
+            let editor_button =
+                ui.button(RichText::new("Board Editor").font(FontId::proportional(30.0)));
+            if ui
+                .button(RichText::new("Game Library").font(FontId::proportional(30.0)))
+                .clicked()
+            {
+                state.set(GameState::Library);
+            }
+            if ui
+                .button(RichText::new("Settings").font(FontId::proportional(30.0)))
+                .clicked()
+            {
+                show_settings.0 = true;
+            }
+
+            ui.add_space(20.0);
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Variant")
+                    .selected_text(game_rule.variant.name())
+                    .show_ui(ui, |ui| {
+                        for variant in Variant::ALL {
+                            ui.selectable_value(&mut game_rule.variant, variant, variant.name());
+                        }
+                    });
+                let chess960 = game_rule.variant == Variant::Chess960;
+                ui.add_enabled(
+                    chess960,
+                    egui::DragValue::new(&mut game_rule.chess960_position).range(0..=959),
+                );
+                if ui
+                    .add_enabled(chess960, egui::Button::new("Random"))
+                    .clicked()
+                {
+                    game_rule.chess960_position = rand::thread_rng().gen_range(0..960);
+                }
+            });
+            ui.add_enabled_ui(game_rule.variant == Variant::Standard, |ui| {
+                egui::ComboBox::from_label("AI Handicap")
+                    .selected_text(game_rule.handicap.name())
+                    .show_ui(ui, |ui| {
+                        for handicap in Handicap::ALL {
+                            ui.selectable_value(&mut game_rule.handicap, handicap, handicap.name());
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.label("Engine:");
+                let online = game_rule.engine == Engine::StockfishOnline;
+                if ui.radio(online, "Stockfish Online").clicked() {
+                    game_rule.engine = Engine::StockfishOnline;
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    if ui.radio(!online, "Local UCI").clicked() && online {
+                        game_rule.engine = Engine::Uci(String::from("stockfish"));
+                    }
+                    if let Engine::Uci(path) = &mut game_rule.engine {
+                        ui.text_edit_singleline(path);
+                    }
+                }
+            });
+            if !slots.names.is_empty() {
+                egui::CollapsingHeader::new("Saved Games").show(ui, |ui| {
+                    let mut deleted = false;
+                    for name in slots.names.iter() {
+                        ui.horizontal(|ui| {
+                            ui.label(name);
+                            if ui.button("Load").clicked() {
+                                load = Some(name.clone());
+                            }
+                            if ui.button("Delete").clicked() {
+                                saves::delete(name);
+                                deleted = true;
+                            }
+                        });
+                    }
+                    if deleted {
+                        slots.refresh();
+                    }
+                });
+            }
+            if let Some(error) = &slots.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            let starts_game = hotseat_button.clicked()
+                || vs_ai_button.clicked()
+                || ai_vs_ai_button.clicked()
+                || analysis_button.clicked();
+            if starts_game {
+                // handicaps only make sense against the AI and from the standard start position
+                let handicap = if vs_ai_button.clicked() && game_rule.variant == Variant::Standard {
+                    game_rule.handicap
+                } else {
+                    Handicap::None
+                };
+                board.new_game(&game_rule, handicap);
+                side_to_move.0 = board.side_to_move();
+                *last_50 = crate::Last50::default();
+                flip_board.0 = false;
+            }
 
             if hotseat_button.clicked() {
                 up_ev.send(TurnEndEvent);
                 state.set(GameState::Playing);
                 game_mode.set(GameMode::Hotseat);
-                switch_sides.0 = true;
+                switch_sides.0 = game_rule.auto_rotate;
             }
 
             if vs_ai_button.clicked() {
@@ -48,8 +192,63 @@ pub fn main_menu(
                 game_mode.set(GameMode::Sim);
                 switch_sides.0 = false;
             }
+
+            if analysis_button.clicked() {
+                up_ev.send(TurnEndEvent);
+                state.set(GameState::Playing);
+                game_mode.set(GameMode::Analysis);
+                switch_sides.0 = false;
+            }
+
+            if puzzle_button.clicked() {
+                if !puzzles.is_loaded() {
+                    puzzles.load();
+                }
+                if puzzles.is_loaded() {
+                    puzzles.next(&mut board);
+                } else {
+                    board.reset(chess::Board::default());
+                }
+                side_to_move.0 = board.side_to_move();
+                up_ev.send(TurnEndEvent);
+                state.set(GameState::Playing);
+                game_mode.set(GameMode::Puzzle);
+                switch_sides.0 = false;
+                // puzzles are shown from the solver's side
+                flip_board.0 = puzzles.solver() == Some(chess::Color::Black);
+            }
+
+            if editor_button.clicked() {
+                up_ev.send(TurnEndEvent);
+                state.set(GameState::Editor);
+                switch_sides.0 = false;
+                flip_board.0 = false;
+            }
         });
     });
+
+    let Some(name) = load else {
+        return;
+    };
+    let restored = SavedGame::load(&name).and_then(|saved| {
+        saved.restore(
+            &mut board,
+            &mut last_50,
+            &mut game_rule,
+            &mut switch_sides,
+            &mut flip_board,
+        )
+    });
+    match restored {
+        Some(mode) => {
+            slots.error = None;
+            side_to_move.0 = board.side_to_move();
+            up_ev.send(TurnEndEvent);
+            state.set(GameState::Playing);
+            game_mode.set(mode);
+        }
+        None => slots.error = Some(format!("Couldn't load the game \"{name}\"")),
+    }
 }
 
 pub fn end_screen(
@@ -58,7 +257,10 @@ pub fn end_screen(
     mut state: ResMut<NextState<GameState>>,
     mut up_ev: EventWriter<TurnEndEvent>,
     mut board: ResMut<crate::game::Board>,
+    mut side_to_move: ResMut<SideToMove>,
     mut last_50: ResMut<crate::Last50>,
+    mut game_mode: ResMut<NextState<GameMode>>,
+    mut replay: ResMut<Replay>,
     result: Res<GameResult>,
     drawn: Query<
         Entity,
@@ -66,14 +268,18 @@ pub fn end_screen(
             With<crate::Piece>,
             With<crate::Square>,
             With<crate::Selector>,
+            With<crate::Pocket>,
+            With<crate::Highlight>,
         )>,
     >,
 ) {
-    let header = match *result {
-        GameResult::Ongoing => unreachable!(),
-        GameResult::Checkmate { winner } => format!("Winner: {:?}", winner),
-        GameResult::Stalemate => String::from("Draw"),
+    let header = match result.winner() {
+        Some(winner) => format!("Winner: {:?}", winner),
+        None => String::from("Draw"),
     };
+    let live = board.live();
+    let moves = board.tree().ply(live).div_ceil(2);
+    let fen = board.tree().fen(live, false);
     let ctx = contexts.ctx_mut();
     egui::SidePanel::right("")
         .show_separator_line(false)
@@ -83,21 +289,39 @@ pub fn end_screen(
             ui.vertical_centered(|ui| {
                 ui.add_space(301.0 - 100.0);
                 ui.heading(RichText::new(header).font(FontId::proportional(40.0)));
+                ui.label(RichText::new(result.description()).font(FontId::proportional(20.0)));
+                ui.label(format!("{} moves", moves));
+                ui.add(egui::Label::new(RichText::new(fen).monospace()).wrap());
+                if ui.button("Copy PGN").clicked() {
+                    ui.ctx().copy_text(crate::pgn::export(board.tree()));
+                }
+                ui.add_space(10.0);
 
                 let go_again = ui.button(RichText::new("Restart").font(FontId::proportional(30.0)));
+                let watch_replay =
+                    ui.button(RichText::new("Watch Replay").font(FontId::proportional(30.0)));
                 let return_to_menu =
                     ui.button(RichText::new("Return to Menu").font(FontId::proportional(30.0)));
 
                 if go_again.clicked() {
                     up_ev.send(TurnEndEvent);
                     state.set(GameState::Playing);
-                    *board = crate::game::Board::default();
-                    *last_50 = crate::Last50::default();
+                    board.restart();
+                    side_to_move.0 = board.side_to_move();
+                    *last_50 = crate::Last50::following(&board);
                     for e in drawn.iter() {
                         commands.entity(e).despawn_recursive();
                     }
                 }
 
+                if watch_replay.clicked() {
+                    replay.begin(&mut board);
+                    side_to_move.0 = board.side_to_move();
+                    up_ev.send(TurnEndEvent);
+                    state.set(GameState::Playing);
+                    game_mode.set(GameMode::Replay);
+                }
+
                 if return_to_menu.clicked() {
                     *board = crate::game::Board::default();
                     *last_50 = crate::Last50::default();
@@ -110,10 +334,169 @@ pub fn end_screen(
         });
 }
 
+pub fn board_editor(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut editor: ResMut<Editor>,
+    mut state: ResMut<NextState<GameState>>,
+    mut game_mode: ResMut<NextState<GameMode>>,
+    mut board: ResMut<crate::game::Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut switch_sides: ResMut<SwitchSides>,
+    game_rule: Res<GameRule>,
+    mut last_50: ResMut<crate::Last50>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+    squares: Query<Entity, With<crate::Square>>,
+) {
+    let mut redraw = false;
+    let mut start = None;
+    let mut leave = false;
+
+    // only changes to the pieces require redrawing the editor
+    let ed = editor.bypass_change_detection();
+    let ctx = contexts.ctx_mut();
+    egui::SidePanel::right("")
+        .show_separator_line(false)
+        .resizable(false)
+        .exact_width(316.)
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading(RichText::new("Board Editor").font(FontId::proportional(40.0)));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("To move:");
+                ui.radio_value(&mut ed.side_to_move, chess::Color::White, "White");
+                ui.radio_value(&mut ed.side_to_move, chess::Color::Black, "Black");
+            });
+
+            egui::Grid::new("castling").show(ui, |ui| {
+                for color in chess::ALL_COLORS {
+                    let rights = &mut ed.castling[color.to_index()];
+                    ui.label(format!("{:?}", color));
+                    ui.checkbox(&mut rights[0], "O-O");
+                    ui.checkbox(&mut rights[1], "O-O-O");
+                    ui.end_row();
+                }
+            });
+
+            let file_name =
+                |file: chess::File| ((b'a' + file.to_index() as u8) as char).to_string();
+            egui::ComboBox::from_label("En passant")
+                .selected_text(ed.en_passant.map_or(String::from("-"), file_name))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut ed.en_passant, None, "-");
+                    for file in chess::ALL_FILES {
+                        ui.selectable_value(&mut ed.en_passant, Some(file), file_name(file));
+                    }
+                });
+
+            ui.horizontal(|ui| {
+                if ui.button("Clear").clicked() {
+                    ed.clear();
+                    redraw = true;
+                }
+                if ui.button("Starting Position").clicked() {
+                    ed.load(&chess::Board::default());
+                    redraw = true;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut ed.fen);
+                if ui.button("Load FEN").clicked() {
+                    match chess::Board::from_str(ed.fen.trim()) {
+                        Ok(fen_board) => {
+                            let halfmove_clock = fen_halfmove_clock(&ed.fen);
+                            let fullmove_number = fen_fullmove_number(&ed.fen);
+                            ed.load(&fen_board);
+                            ed.halfmove_clock = halfmove_clock;
+                            ed.fullmove_number = fullmove_number;
+                            redraw = true;
+                        }
+                        Err(e) => ed.error = Some(e.to_string()),
+                    }
+                }
+            });
+
+            if let Some(error) = &ed.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+
+            ui.vertical_centered(|ui| {
+                ui.add_space(20.0);
+                for (label, mode) in [
+                    ("Play Hotseat", GameMode::Hotseat),
+                    ("Play vs. AI", GameMode::VsAi),
+                    ("Watch AI vs. AI", GameMode::Sim),
+                    ("Analysis Board", GameMode::Analysis),
+                ] {
+                    if ui
+                        .button(RichText::new(label).font(FontId::proportional(30.0)))
+                        .clicked()
+                    {
+                        start = Some(mode);
+                    }
+                }
+                if ui
+                    .button(RichText::new("Return to Menu").font(FontId::proportional(30.0)))
+                    .clicked()
+                {
+                    leave = true;
+                }
+            });
+        });
+
+    if let Some(mode) = start {
+        match editor.build() {
+            Ok(start_board) => {
+                board.reset(start_board);
+                board.tree_mut().set_halfmove_clock(editor.halfmove_clock);
+                board.tree_mut().set_fullmove_number(editor.fullmove_number);
+                side_to_move.0 = start_board.side_to_move();
+                *last_50 = crate::Last50::following(&board);
+                switch_sides.0 = mode == GameMode::Hotseat && game_rule.auto_rotate;
+                game_mode.set(mode);
+                state.set(GameState::Playing);
+                up_ev.send(TurnEndEvent);
+            }
+            Err(e) => editor.bypass_change_detection().error = Some(e),
+        }
+    }
+
+    if leave {
+        state.set(GameState::MainMenu);
+        for e in squares.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+
+    if redraw {
+        editor.set_changed();
+    }
+}
+
 pub fn turn_readout(
     mut contexts: EguiContexts,
     side_to_move: Res<SideToMove>,
+    game_mode: Res<State<GameMode>>,
+    mut board: ResMut<crate::game::Board>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+    opening: Res<CurrentOpening>,
+    mut explorer: ResMut<Explorer>,
+    mut switch_sides: ResMut<SwitchSides>,
+    mut flip_board: ResMut<FlipBoard>,
+    mut show_settings: ResMut<ShowSettings>,
+    mut slots: ResMut<SaveSlots>,
+    game_rule: Res<GameRule>,
+    mut ending: Ending,
+    evaluation: Res<Evaluation>,
 ) {
+    let mut target = None;
+    let mut outcome = None;
+    let mut back_to_live = false;
+    let mut reoriented = false;
     let ctx = contexts.ctx_mut();
     egui::SidePanel::right("")
         .show_separator_line(false)
@@ -121,8 +504,965 @@ pub fn turn_readout(
         .exact_width(316.)
         .show(ctx, |ui| {
             ui.vertical_centered(|ui| {
-                let side = format!("{:?}'s Turn!", side_to_move.0);
-                ui.heading(RichText::new(side).font(FontId::proportional(40.0)));
+                let heading = match **game_mode {
+                    GameMode::Replay => String::from("Replay"),
+                    _ => format!("{:?}'s Turn!", side_to_move.0),
+                };
+                ui.heading(RichText::new(heading).font(FontId::proportional(40.0)));
+                if let Some(opening) = opening.0 {
+                    let name = format!("{} {}", opening.eco, opening.name);
+                    ui.label(RichText::new(name).font(FontId::proportional(20.0)))
+                        .on_hover_text(format!(
+                            "Named from a table of {} common openings, so rarer lines keep \
+                             the name of the last common position",
+                            openings::count()
+                        ));
+                }
+                if board.variant() == Variant::ThreeCheck {
+                    let checks = board.state().checks;
+                    let counter = format!(
+                        "Checks: White {}/{}, Black {}/{}",
+                        checks[0], CHECKS_TO_WIN, checks[1], CHECKS_TO_WIN
+                    );
+                    ui.label(RichText::new(counter).font(FontId::proportional(20.0)));
+                }
+                // Crazyhouse captures go to the pockets, which are drawn by the board
+                if board.variant() != Variant::Crazyhouse {
+                    captured_pieces(ui, &board);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Flip Board").clicked() {
+                        flip_board.0 = !flip_board.0;
+                        reoriented = true;
+                    }
+                    reoriented |= ui.checkbox(&mut switch_sides.0, "Auto-rotate").changed();
+                    if ui.button("Settings").clicked() {
+                        show_settings.0 = true;
+                    }
+                });
+
+                if matches!(**game_mode, GameMode::Hotseat | GameMode::VsAi) {
+                    let live = board.live();
+                    // against the AI it's always the player who resigns or offers a draw
+                    let player = match **game_mode {
+                        GameMode::VsAi => !game_rule.ai_color,
+                        _ => board.tree().node(live).board.side_to_move(),
+                    };
+                    let offer = ending.draw_offer.offered.filter(|&(_, at)| at == live);
+                    ui.horizontal(|ui| {
+                        if ui.button("Resign").clicked() {
+                            outcome = Some(GameResult::Resignation { winner: !player });
+                        }
+                        let offer_draw = egui::Button::new("Offer Draw");
+                        if ui.add_enabled(offer.is_none(), offer_draw).clicked() {
+                            if **game_mode == GameMode::Hotseat {
+                                ending.draw_offer.offered = Some((player, live));
+                            } else if ai::accepts_draw(
+                                &board,
+                                evaluation.in_game(&board),
+                                game_rule.ai_color,
+                            ) {
+                                outcome = Some(GameResult::DrawAgreed);
+                            } else {
+                                ending.draw_offer.declined = Some(live);
+                            }
+                        }
+                    });
+                    if let Some((by, _)) = offer {
+                        ui.label(format!("{:?} offers a draw", by));
+                        ui.horizontal(|ui| {
+                            if ui.button("Accept").clicked() {
+                                outcome = Some(GameResult::DrawAgreed);
+                            }
+                            if ui.button("Decline").clicked() {
+                                ending.draw_offer.offered = None;
+                                ending.draw_offer.declined = Some(live);
+                            }
+                        });
+                    }
+                    if ending.draw_offer.declined == Some(live) {
+                        let declined = match **game_mode {
+                            GameMode::VsAi => {
+                                format!("{} declines the draw", game_rule.engine.name())
+                            }
+                            _ => String::from("The draw was declined"),
+                        };
+                        ui.label(declined);
+                    }
+                }
+            });
+
+            // analysis has its own move tree to navigate with
+            if **game_mode != GameMode::Analysis {
+                move_list(ui, &board, &mut target);
+                ui.vertical_centered(|ui| {
+                    back_to_live = ui
+                        .add_enabled(board.is_reviewing(), egui::Button::new("Back to Live"))
+                        .clicked();
+                });
+            }
+
+            if board.variant() == Variant::Standard {
+                egui::CollapsingHeader::new("Opening Explorer").show(ui, |ui| {
+                    opening_explorer(ui, &board, &mut explorer);
+                });
+            }
+
+            if !matches!(**game_mode, GameMode::Puzzle | GameMode::Replay) {
+                egui::CollapsingHeader::new("Save Game").show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut slots.name);
+                        let name = slots.name.trim().to_string();
+                        // the autosave slot is overwritten on every move
+                        let valid = !name.is_empty() && name != saves::AUTOSAVE;
+                        if ui.add_enabled(valid, egui::Button::new("Save")).clicked() {
+                            let saved = SavedGame::new(
+                                &board,
+                                &game_mode,
+                                &game_rule,
+                                &switch_sides,
+                                &flip_board,
+                            );
+                            slots.error = match saved.map(|saved| saved.save(&name)) {
+                                Some(Ok(())) => None,
+                                Some(Err(e)) => Some(e),
+                                None => Some(String::from("This game can't be saved")),
+                            };
+                            slots.refresh();
+                        }
+                    });
+                    if ui.button("Add to Library").clicked() {
+                        slots.error = library::add(board.tree_mut(), &game_mode, &game_rule).err();
+                    }
+                    if let Some(error) = &slots.error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                });
+            }
+        });
+
+    if let Some(outcome) = outcome {
+        ending.finish(&mut board, outcome);
+        *selected_piece = SelectedPiece::None;
+        return;
+    }
+    if reoriented {
+        up_ev.send(TurnEndEvent);
+    }
+    if target.is_none() && !back_to_live {
+        return;
+    }
+    match target {
+        Some(id) => board.review(id),
+        None => board.back_to_live(),
+    }
+    *selected_piece = SelectedPiece::None;
+    up_ev.send(TurnEndEvent);
+}
+
+/// Lists the moves leading to the live position in two columns, selecting the
+/// one clicked for review
+fn move_list(ui: &mut egui::Ui, board: &crate::game::Board, target: &mut Option<NodeId>) {
+    let tree = board.tree();
+    let path = tree.path(board.live());
+    egui::ScrollArea::vertical()
+        .max_height(240.0)
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            egui::Grid::new("move_list")
+                .num_columns(3)
+                .min_col_width(60.0)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (i, &id) in path.iter().enumerate().skip(1) {
+                        let (number, white) = crate::pgn::move_number(tree, id);
+                        if white || i == 1 {
+                            ui.label(format!("{}.", number));
+                        }
+                        if !white && i == 1 {
+                            ui.label("...");
+                        }
+                        let shown = tree.current() == id;
+                        if ui.selectable_label(shown, &tree.node(id).san).clicked() {
+                            *target = Some(id);
+                        }
+                        if !white {
+                            ui.end_row();
+                        }
+                    }
+                });
+        });
+}
+
+/// Shows the pieces each side has captured and who is ahead in material
+fn captured_pieces(ui: &mut egui::Ui, board: &crate::game::Board) {
+    let balance = material::balance(board);
+    for color in chess::ALL_COLORS {
+        let taken: String = material::captured(board.tree(), board.tree().current(), !color)
+            .into_iter()
+            .map(|piece| piece_glyph(piece, !color))
+            .collect();
+        let advantage = match color {
+            chess::Color::White => balance,
+            chess::Color::Black => -balance,
+        };
+        let mut text = format!("{:?}: {}", color, taken);
+        if advantage > 0 {
+            text.push_str(&format!(" +{}", advantage));
+        }
+        ui.label(RichText::new(text).font(FontId::proportional(20.0)));
+    }
+}
+
+/// The Unicode chess symbol for a piece
+fn piece_glyph(piece: chess::Piece, color: chess::Color) -> char {
+    match (piece, color) {
+        (chess::Piece::Pawn, chess::Color::White) => '♙',
+        (chess::Piece::Knight, chess::Color::White) => '♘',
+        (chess::Piece::Bishop, chess::Color::White) => '♗',
+        (chess::Piece::Rook, chess::Color::White) => '♖',
+        (chess::Piece::Queen, chess::Color::White) => '♕',
+        (chess::Piece::King, chess::Color::White) => '♔',
+        (chess::Piece::Pawn, chess::Color::Black) => '♟',
+        (chess::Piece::Knight, chess::Color::Black) => '♞',
+        (chess::Piece::Bishop, chess::Color::Black) => '♝',
+        (chess::Piece::Rook, chess::Color::Black) => '♜',
+        (chess::Piece::Queen, chess::Color::Black) => '♛',
+        (chess::Piece::King, chess::Color::Black) => '♚',
+    }
+}
+
+/// Lists the moves played from the current position in the imported games
+fn opening_explorer(ui: &mut egui::Ui, board: &crate::game::Board, explorer: &mut Explorer) {
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut explorer.path);
+        if ui
+            .add_enabled(!explorer.importing, egui::Button::new("Import"))
+            .clicked()
+        {
+            explorer.start_import();
+        }
+    });
+    if explorer.importing {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label("Indexing games...");
+        });
+    }
+    if let Some(error) = &explorer.error {
+        ui.colored_label(egui::Color32::LIGHT_RED, error);
+    }
+
+    let moves = board
+        .to_board()
+        .map(|position| explorer.index.moves(&position))
+        .unwrap_or_default();
+    if moves.is_empty() {
+        ui.label(format!(
+            "No games from this position ({} indexed)",
+            explorer.index.games
+        ));
+        return;
+    }
+
+    let legal = board.legal_moves();
+    egui::Grid::new("explorer").striped(true).show(ui, |ui| {
+        ui.label("Move");
+        ui.label("Games");
+        ui.label("White / Draw / Black");
+        ui.label("Avg");
+        ui.end_row();
+        for (m, stats) in moves {
+            let [white, draw, black] = stats.percentages();
+            ui.label(crate::pgn::san(board, m, &legal, false, false));
+            ui.label(stats.games.to_string());
+            ui.label(format!("{:.0}% / {:.0}% / {:.0}%", white, draw, black));
+            ui.label(
+                stats
+                    .average_rating()
+                    .map_or(String::from("-"), |r| r.to_string()),
+            );
+            ui.end_row();
+        }
+    });
+}
+
+pub fn move_tree(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut state: ResMut<NextState<GameState>>,
+    mut board: ResMut<crate::game::Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+    drawn: Query<
+        Entity,
+        Or<(
+            With<crate::Piece>,
+            With<crate::Square>,
+            With<crate::Selector>,
+            With<crate::Pocket>,
+            With<crate::Highlight>,
+        )>,
+    >,
+) {
+    let before = board.tree().current();
+    let mut target = None;
+    let ctx = contexts.ctx_mut();
+    egui::SidePanel::left("move_tree")
+        .show_separator_line(false)
+        .resizable(false)
+        .exact_width(316.)
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading(RichText::new("Analysis").font(FontId::proportional(40.0)));
+            });
+
+            ui.horizontal(|ui| {
+                if ui.button("|<").clicked() {
+                    board.tree_mut().to_start();
+                }
+                if ui.button("<").clicked() {
+                    board.tree_mut().back();
+                }
+                if ui.button(">").clicked() {
+                    board.tree_mut().forward();
+                }
+                if ui.button(">|").clicked() {
+                    board.tree_mut().to_end();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let current = board.tree().current();
+                if ui.button("Promote").clicked() {
+                    board.tree_mut().promote(current);
+                }
+                if ui.button("Delete").clicked() {
+                    board.tree_mut().delete(current);
+                }
+                if ui.button("Copy PGN").clicked() {
+                    ui.ctx().copy_text(crate::pgn::export(board.tree()));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                for nag in 1..=6 {
+                    let active = board.tree().current_node().nags.contains(&nag);
+                    let symbol = crate::pgn::nag_symbol(nag).unwrap();
+                    if ui.selectable_label(active, symbol).clicked() {
+                        board.tree_mut().toggle_nag(nag);
+                    }
+                }
+            });
+
+            // the comment box borrows the board every frame, which only counts as a
+            // change once something is typed
+            let comment = ui.add(
+                egui::TextEdit::multiline(
+                    &mut board
+                        .bypass_change_detection()
+                        .tree_mut()
+                        .current_node_mut()
+                        .comment,
+                )
+                .hint_text("Comment")
+                .desired_rows(2),
+            );
+            if comment.changed() {
+                board.set_changed();
+            }
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(ui.available_height() - 40.0)
+                .show(ui, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        move_tree_line(ui, board.tree(), GameTree::ROOT, true, &mut target);
+                    });
+                });
+
+            if ui.button("Return to Menu").clicked() {
+                *board = crate::game::Board::default();
+                state.set(GameState::MainMenu);
+                for e in drawn.iter() {
+                    commands.entity(e).despawn_recursive();
+                }
+            }
+        });
+
+    if let Some(id) = target {
+        board.tree_mut().go_to(id);
+    }
+    if board.tree().current() != before {
+        side_to_move.0 = board.side_to_move();
+        *selected_piece = SelectedPiece::None;
+        up_ev.send(TurnEndEvent);
+    }
+}
+
+/// Lays out the continuation after `from`, with each variation in parentheses
+fn move_tree_line(
+    ui: &mut egui::Ui,
+    tree: &GameTree,
+    from: NodeId,
+    mut force_number: bool,
+    target: &mut Option<NodeId>,
+) {
+    let mut node = from;
+    while let Some((&main, variations)) = tree.node(node).children.split_first() {
+        force_number = move_tree_move(ui, tree, main, force_number, target);
+        for &variation in variations {
+            ui.label("(");
+            move_tree_move(ui, tree, variation, true, target);
+            move_tree_line(ui, tree, variation, false, target);
+            ui.label(")");
+            force_number = true;
+        }
+        node = main;
+    }
+}
+
+/// Returns true if the next move has to repeat its move number
+fn move_tree_move(
+    ui: &mut egui::Ui,
+    tree: &GameTree,
+    id: NodeId,
+    force_number: bool,
+    target: &mut Option<NodeId>,
+) -> bool {
+    let node = tree.node(id);
+    let (number, white) = crate::pgn::move_number(tree, id);
+    let mut text = if white {
+        format!("{}. ", number)
+    } else if force_number {
+        format!("{}... ", number)
+    } else {
+        String::new()
+    };
+    text.push_str(&node.san);
+    for &nag in node.nags.iter() {
+        match crate::pgn::nag_symbol(nag) {
+            Some(symbol) => text.push_str(symbol),
+            None => text.push_str(&format!(" ${}", nag)),
+        }
+    }
+
+    let mut text = RichText::new(text);
+    if !tree.is_main_line(id) {
+        text = text.weak();
+    }
+    if ui.selectable_label(tree.current() == id, text).clicked() {
+        *target = Some(id);
+    }
+
+    if !node.comment.is_empty() {
+        ui.label(RichText::new(&node.comment).italics());
+        return true;
+    }
+    false
+}
+
+pub fn puzzle_panel(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut state: ResMut<NextState<GameState>>,
+    mut board: ResMut<crate::game::Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut puzzles: ResMut<PuzzleSession>,
+    mut flip_board: ResMut<FlipBoard>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+    drawn: Query<
+        Entity,
+        Or<(
+            With<crate::Piece>,
+            With<crate::Square>,
+            With<crate::Selector>,
+            With<crate::Pocket>,
+            With<crate::Highlight>,
+        )>,
+    >,
+) {
+    let mut restart = false;
+    let ctx = contexts.ctx_mut();
+    egui::SidePanel::left("puzzle")
+        .show_separator_line(false)
+        .resizable(false)
+        .exact_width(316.)
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading(RichText::new("Puzzles").font(FontId::proportional(40.0)));
+                let rating = format!(
+                    "Rating: {:.0} ± {:.0}",
+                    puzzles.rating.rating, puzzles.rating.deviation
+                );
+                ui.label(RichText::new(rating).font(FontId::proportional(20.0)));
+            });
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut puzzles.path);
+                if ui.button("Load").clicked() {
+                    puzzles.load();
+                    restart = puzzles.is_loaded() && puzzles.next(&mut board);
+                }
+            });
+
+            let selected = puzzles.theme.clone().unwrap_or_else(|| String::from("Any"));
+            let mut theme = puzzles.theme.clone();
+            egui::ComboBox::from_label("Theme")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut theme, None, "Any");
+                    for name in puzzles.themes.iter() {
+                        ui.selectable_value(&mut theme, Some(name.clone()), name);
+                    }
+                });
+            puzzles.theme = theme;
+
+            if let Some(error) = &puzzles.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+            ui.separator();
+
+            if let (Some(puzzle), Some(solver)) = (puzzles.puzzle(), puzzles.solver()) {
+                match puzzles.status {
+                    PuzzleStatus::Solving => {
+                        ui.label(format!("Find the best move for {:?}", solver));
+                    }
+                    PuzzleStatus::Wrong => {
+                        ui.colored_label(
+                            egui::Color32::LIGHT_RED,
+                            "That's not the move! Try something else.",
+                        );
+                    }
+                    PuzzleStatus::Solved => {
+                        ui.colored_label(egui::Color32::LIGHT_GREEN, "Solved!");
+                        ui.label(format!("Puzzle {} rated {:.0}", puzzle.id, puzzle.rating));
+                        ui.label(puzzle.themes.join(", "));
+                    }
+                }
+            }
+
+            ui.vertical_centered(|ui| {
+                ui.add_space(20.0);
+                if ui
+                    .add_enabled(
+                        puzzles.puzzle().is_some(),
+                        egui::Button::new(RichText::new("Retry").font(FontId::proportional(30.0))),
+                    )
+                    .clicked()
+                {
+                    puzzles.retry(&mut board);
+                    restart = true;
+                }
+                if ui
+                    .add_enabled(
+                        puzzles.is_loaded(),
+                        egui::Button::new(
+                            RichText::new("Next Puzzle").font(FontId::proportional(30.0)),
+                        ),
+                    )
+                    .clicked()
+                {
+                    restart = puzzles.next(&mut board);
+                }
+                if ui
+                    .button(RichText::new("Return to Menu").font(FontId::proportional(30.0)))
+                    .clicked()
+                {
+                    *board = crate::game::Board::default();
+                    state.set(GameState::MainMenu);
+                    for e in drawn.iter() {
+                        commands.entity(e).despawn_recursive();
+                    }
+                }
+            });
+        });
+
+    if restart {
+        side_to_move.0 = board.side_to_move();
+        flip_board.0 = puzzles.solver() == Some(chess::Color::Black);
+        *selected_piece = SelectedPiece::None;
+        up_ev.send(TurnEndEvent);
+    }
+}
+
+/// The width of a column in the library list
+fn column_width(column: Column) -> f32 {
+    match column {
+        Column::White | Column::Black => 180.0,
+        Column::Date => 100.0,
+        Column::Result => 70.0,
+        Column::Opening => 380.0,
+        Column::Moves => 60.0,
+    }
+}
+
+/// Lists the games in the library, opening the one picked for replay
+pub fn library_screen(
+    mut contexts: EguiContexts,
+    mut state: ResMut<NextState<GameState>>,
+    mut game_mode: ResMut<NextState<GameMode>>,
+    mut library: ResMut<Library>,
+    mut board: ResMut<crate::game::Board>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut switch_sides: ResMut<SwitchSides>,
+    mut flip_board: ResMut<FlipBoard>,
+    mut replay: ResMut<Replay>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+) {
+    let mut open = None;
+    let mut sort = None;
+    let mut filtered = false;
+    let ctx = contexts.ctx_mut();
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.vertical_centered(|ui| {
+            ui.heading(RichText::new("Game Library").font(FontId::proportional(40.0)));
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Back").clicked() {
+                state.set(GameState::MainMenu);
+            }
+            ui.label("PGN file:");
+            ui.text_edit_singleline(&mut library.import_path);
+            if ui
+                .add_enabled(!library.importing, egui::Button::new("Import"))
+                .clicked()
+            {
+                library.start_import();
+            }
+            if ui.button("Replay").clicked() {
+                open = Some(library::read_file(&library.import_path));
+            }
+            if library.importing {
+                ui.spinner();
+            }
+        });
+        if let Some(message) = &library.message {
+            ui.label(message);
+        }
+        if let Some(error) = &library.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        ui.horizontal(|ui| {
+            let mut filter = |ui: &mut egui::Ui, label: &str, text: &mut String| {
+                ui.label(label);
+                let field = egui::TextEdit::singleline(text).desired_width(140.0);
+                filtered |= ui.add(field).changed();
+            };
+            filter(ui, "Player:", &mut library.player);
+            filter(ui, "Opening:", &mut library.opening);
+            filter(ui, "Date:", &mut library.date);
+            egui::ComboBox::from_label("Result")
+                .selected_text(library.result.unwrap_or("Any"))
+                .show_ui(ui, |ui| {
+                    for result in [None, Some("1-0"), Some("0-1"), Some("1/2-1/2"), Some("*")] {
+                        filtered |= ui
+                            .selectable_value(&mut library.result, result, result.unwrap_or("Any"))
+                            .changed();
+                    }
+                });
+        });
+        ui.label(format!(
+            "{} of {} games",
+            library.shown.len(),
+            library.entries.len()
+        ));
+        ui.separator();
+
+        let row_height = ui.spacing().interact_size.y;
+        ui.horizontal(|ui| {
+            for column in Column::ALL {
+                let mut text = column.name().to_string();
+                if library.sort == column {
+                    text.push_str(if library.descending { " ▼" } else { " ▲" });
+                }
+                let header = egui::SelectableLabel::new(library.sort == column, text);
+                if ui
+                    .add_sized([column_width(column), row_height], header)
+                    .clicked()
+                {
+                    sort = Some(column);
+                }
+            }
+        });
+        // only the rows in view are laid out, however many games there are
+        egui::ScrollArea::vertical().auto_shrink(false).show_rows(
+            ui,
+            row_height,
+            library.shown.len(),
+            |ui, rows| {
+                for &index in &library.shown[rows] {
+                    let entry = &library.entries[index];
+                    ui.horizontal(|ui| {
+                        for column in Column::ALL {
+                            let text = egui::Label::new(entry.text(column)).truncate();
+                            ui.add_sized([column_width(column), row_height], text);
+                        }
+                        if ui.button("Open").clicked() {
+                            open = Some(library.open(index));
+                        }
+                        if let Some(slot) = entry.slot() {
+                            ui.label(format!("Saved as \"{slot}\""));
+                        }
+                    });
+                }
+            },
+        );
+    });
+
+    if let Some(column) = sort {
+        library.sort_by(column);
+    } else if filtered {
+        library.update_shown();
+    }
+    let Some(opened) = open else {
+        return;
+    };
+    match opened {
+        Ok(tree) => {
+            board.load(tree);
+            replay.begin(&mut board);
+            side_to_move.0 = board.side_to_move();
+            switch_sides.0 = false;
+            flip_board.0 = false;
+            up_ev.send(TurnEndEvent);
+            state.set(GameState::Playing);
+            game_mode.set(GameMode::Replay);
+        }
+        Err(e) => library.error = Some(e),
+    }
+}
+
+/// Describes the game being replayed, with controls to play it back and the way
+/// back to the library
+pub fn replay_panel(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut state: ResMut<NextState<GameState>>,
+    mut board: ResMut<crate::game::Board>,
+    mut replay: ResMut<Replay>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+    drawn: Query<
+        Entity,
+        Or<(
+            With<crate::Piece>,
+            With<crate::Square>,
+            With<crate::Selector>,
+            With<crate::Pocket>,
+            With<crate::Highlight>,
+        )>,
+    >,
+) {
+    let before = board.tree().current();
+    let positions = replay::positions(&board);
+    let shown = positions
+        .iter()
+        .position(|&id| id == before)
+        .unwrap_or_default();
+    let mut target = None;
+    let mut leave = None;
+    let ctx = contexts.ctx_mut();
+    egui::SidePanel::left("replay")
+        .show_separator_line(false)
+        .resizable(false)
+        .exact_width(316.)
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                let tree = board.tree();
+                let players = format!(
+                    "{} vs. {}",
+                    tree.tag("White").unwrap_or("?"),
+                    tree.tag("Black").unwrap_or("?")
+                );
+                ui.heading(RichText::new(players).font(FontId::proportional(30.0)));
+                for name in ["Event", "Date", "Result", "Opening"] {
+                    if let Some(value) = tree.tag(name) {
+                        let text = format!("{}: {}", name, value);
+                        ui.label(RichText::new(text).font(FontId::proportional(20.0)));
+                    }
+                }
+
+                ui.add_space(20.0);
+                let last = positions.len() - 1;
+                ui.horizontal(|ui| {
+                    if ui.button("Start").clicked() {
+                        target = Some(0);
+                    }
+                    if ui.button("Back").clicked() {
+                        target = Some(shown.saturating_sub(1));
+                    }
+                    let play = if replay.playing { "Pause" } else { "Play" };
+                    if ui.button(play).clicked() {
+                        replay.toggle(&mut board);
+                    }
+                    if ui.button("Forward").clicked() {
+                        target = Some((shown + 1).min(last));
+                    }
+                    if ui.button("End").clicked() {
+                        target = Some(last);
+                    }
+                });
+                let mut ply = shown;
+                if ui
+                    .add(egui::Slider::new(&mut ply, 0..=last).text("Half move"))
+                    .changed()
+                {
+                    target = Some(ply);
+                }
+                ui.add(
+                    egui::Slider::new(&mut replay.speed, 0.25..=4.0)
+                        .logarithmic(true)
+                        .text("Speed"),
+                );
+
+                ui.add_space(20.0);
+                if ui
+                    .button(RichText::new("Back to Library").font(FontId::proportional(30.0)))
+                    .clicked()
+                {
+                    leave = Some(GameState::Library);
+                }
+                if ui
+                    .button(RichText::new("Return to Menu").font(FontId::proportional(30.0)))
+                    .clicked()
+                {
+                    leave = Some(GameState::MainMenu);
+                }
+            });
+        });
+
+    if let Some(next) = leave {
+        *board = crate::game::Board::default();
+        replay.playing = false;
+        state.set(next);
+        for e in drawn.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+        return;
+    }
+    // stepping by hand takes over from playing
+    if let Some(ply) = target {
+        replay.playing = false;
+        board.review(positions[ply]);
+    }
+    if board.tree().current() != before {
+        side_to_move.0 = board.side_to_move();
+        *selected_piece = SelectedPiece::None;
+        up_ev.send(TurnEndEvent);
+    }
+}
+
+/// Asks which piece a pawn reaching the last rank promotes to
+pub fn promotion_picker(
+    mut contexts: EguiContexts,
+    mut board: ResMut<crate::game::Board>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    mut side_to_move: ResMut<SideToMove>,
+    mut last_50: ResMut<crate::Last50>,
+    mut up_ev: EventWriter<TurnEndEvent>,
+) {
+    let SelectedPiece::Promotion { source, dest } = *selected_piece else {
+        return;
+    };
+    let mut choice = None;
+    let mut cancel = false;
+    egui::Window::new("Promote to")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                for piece in crate::game::promotions(&board, source, dest) {
+                    if ui.button(format!("{:?}", piece)).clicked() {
+                        choice = Some(piece);
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    cancel = true;
+                }
+            });
+        });
+
+    if let Some(piece) = choice {
+        crate::game::promote(
+            piece,
+            &mut board,
+            &mut selected_piece,
+            &mut side_to_move,
+            &mut last_50,
+        );
+        up_ev.send(TurnEndEvent);
+    } else if cancel {
+        *selected_piece = SelectedPiece::None;
+    }
+}
+
+/// Preferences, opened from the main menu or during a game and saved as they change
+pub fn settings_window(
+    mut contexts: EguiContexts,
+    mut show_settings: ResMut<ShowSettings>,
+    mut theme: ResMut<Theme>,
+    mut animation: ResMut<MoveAnimation>,
+    mut coordinates: ResMut<Coordinates>,
+    mut audio: ResMut<AudioSettings>,
+    mut game_rule: ResMut<GameRule>,
+) {
+    if !show_settings.0 {
+        return;
+    }
+    // the theme is only written to when it changes, as every change redraws the board
+    let mut palette = theme.palette;
+    let mut piece_set = theme.piece_set.clone();
+    egui::Window::new("Settings")
+        .open(&mut show_settings.0)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ComboBox::from_label("Board")
+                .selected_text(palette.name)
+                .show_ui(ui, |ui| {
+                    for option in PALETTES.iter() {
+                        ui.selectable_value(&mut palette, option, option.name);
+                    }
+                });
+            egui::ComboBox::from_label("Pieces")
+                .selected_text(&piece_set)
+                .show_ui(ui, |ui| {
+                    for option in theme.piece_sets.iter() {
+                        ui.selectable_value(&mut piece_set, option.clone(), option);
+                    }
+                });
+            egui::ComboBox::from_label("Coordinates")
+                .selected_text(coordinates.name())
+                .show_ui(ui, |ui| {
+                    for option in Coordinates::ALL {
+                        ui.selectable_value(&mut *coordinates, option, option.name());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut animation.seconds, 0.0..=1.0).text("Move animation (s)"));
+            ui.checkbox(&mut game_rule.auto_rotate, "Auto-rotate in hotseat games");
+            ui.horizontal(|ui| {
+                ui.label("AI plays:");
+                ui.radio_value(&mut game_rule.ai_color, chess::Color::White, "White");
+                ui.radio_value(&mut game_rule.ai_color, chess::Color::Black, "Black");
+            });
+            ui.horizontal(|ui| {
+                ui.add_enabled(
+                    !audio.muted,
+                    egui::Slider::new(&mut audio.volume, 0.0..=1.0).text("Volume"),
+                );
+                ui.checkbox(&mut audio.muted, "Mute");
             });
         });
+    if palette != theme.palette {
+        theme.palette = palette;
+    }
+    if piece_set != theme.piece_set {
+        theme.piece_set = piece_set;
+    }
 }
@@ -0,0 +1,104 @@
+//! Antichess: captures are compulsory, the king is an ordinary piece and the side
+//! that runs out of pieces or moves wins
+
+use chess::{CastleRights, ChessMove, Piece};
+
+use super::{Position, Rules, State};
+use crate::GameResult;
+
+/// Pawns may also promote to a king
+const PROMOTIONS: [Piece; 5] = [
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::King,
+];
+
+/// The standard start position without castling
+pub fn start_position() -> Position {
+    let mut position = Position::default();
+    for color in chess::ALL_COLORS {
+        position.set_castle_rights(color, CastleRights::NoRights);
+    }
+    position
+}
+
+pub struct Antichess;
+
+impl Rules for Antichess {
+    fn legal_moves(&self, position: &Position, _state: &State) -> Vec<ChessMove> {
+        let moves = position.pseudo_legal_moves(&PROMOTIONS);
+        let captures: Vec<ChessMove> = moves
+            .iter()
+            .copied()
+            .filter(|&m| position.is_capture(m))
+            .collect();
+        if captures.is_empty() {
+            moves
+        } else {
+            captures
+        }
+    }
+
+    fn play(&self, position: &Position, state: &State, m: ChessMove) -> (Position, State) {
+        (position.make_move(m), *state)
+    }
+
+    fn in_check(&self, _position: &Position, _state: &State) -> bool {
+        false
+    }
+
+    fn outcome(&self, position: &Position, state: &State) -> Option<GameResult> {
+        let side = position.side_to_move();
+        self.legal_moves(position, state)
+            .is_empty()
+            .then_some(GameResult::VariantWin { winner: side })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chess::{Color, Square};
+
+    use super::*;
+
+    fn position(fen: &str) -> Position {
+        chess::Board::from_str(fen).unwrap().into()
+    }
+
+    #[test]
+    fn captures_are_compulsory() {
+        let position = position("rnbqkbnr/p1pppppp/8/1p6/8/4P3/PPPP1PPP/RNBQKBNR w - - 0 2");
+        assert_eq!(
+            Antichess.legal_moves(&position, &State::default()),
+            [ChessMove::new(Square::F1, Square::B5, None)]
+        );
+    }
+
+    #[test]
+    fn pawns_promote_to_kings() {
+        let position = position("8/P7/8/8/8/8/8/k6K w - - 0 1");
+        let m = ChessMove::new(Square::A7, Square::A8, Some(Piece::King));
+        assert!(Antichess
+            .legal_moves(&position, &State::default())
+            .contains(&m));
+    }
+
+    #[test]
+    fn losing_every_piece_wins() {
+        let mut position = position("4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        position.clear_square(Square::E8);
+        assert!(Antichess
+            .legal_moves(&position, &State::default())
+            .is_empty());
+        assert_eq!(
+            Antichess.outcome(&position, &State::default()),
+            Some(GameResult::VariantWin {
+                winner: Color::Black
+            })
+        );
+    }
+}
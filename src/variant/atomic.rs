@@ -0,0 +1,154 @@
+//! Atomic: every capture explodes, removing the capturing piece and every piece
+//! but pawns next to the target square
+//!
+//! Blowing up the enemy king wins. Kings can't capture, and a king standing next
+//! to the enemy king can't be checked, since taking it would blow up both.
+
+use chess::{BitBoard, ChessMove, Color, MoveGen, Piece, EMPTY};
+
+use super::{Position, Rules, State};
+use crate::GameResult;
+
+const PROMOTIONS: [Piece; 4] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight];
+
+/// The squares cleared by the explosion if `m` is a capture
+pub fn explosion(position: &Position, m: ChessMove) -> Option<BitBoard> {
+    if !position.is_capture(m) {
+        return None;
+    }
+    let dest = m.get_dest();
+    let around =
+        chess::get_king_moves(dest) & *position.combined() & !*position.pieces(Piece::Pawn);
+    Some(around | BitBoard::from_square(dest))
+}
+
+fn kings_touch(position: &Position) -> bool {
+    match (
+        position.king_square(Color::White),
+        position.king_square(Color::Black),
+    ) {
+        (Some(white), Some(black)) => {
+            chess::get_king_moves(white) & BitBoard::from_square(black) != EMPTY
+        }
+        _ => false,
+    }
+}
+
+/// True if the king of `color` could be blown up by the other side
+fn attacked(position: &Position, color: Color) -> bool {
+    let Some(king) = position.king_square(color) else {
+        return false;
+    };
+    !kings_touch(position)
+        && position.attackers(king, !color) & !*position.pieces(Piece::King) != EMPTY
+}
+
+pub struct Atomic;
+
+impl Rules for Atomic {
+    fn legal_moves(&self, position: &Position, state: &State) -> Vec<ChessMove> {
+        let color = position.side_to_move();
+        let mut moves: Vec<ChessMove> = position
+            .pseudo_legal_moves(&PROMOTIONS)
+            .into_iter()
+            .filter(|&m| {
+                position.piece_on(m.get_source()) != Some(Piece::King) || !position.is_capture(m)
+            })
+            .collect();
+
+        // castling works as in standard chess, which `chess::Board` can check while both
+        // kings are on the board
+        if let Some(board) = position.to_board() {
+            moves.extend(MoveGen::new_legal(&board).filter(|m| {
+                let files = m.get_source().get_file().to_index() as i32
+                    - m.get_dest().get_file().to_index() as i32;
+                board.piece_on(m.get_source()) == Some(Piece::King) && files.abs() == 2
+            }));
+        }
+
+        moves.retain(|&m| {
+            let (after, _) = self.play(position, state, m);
+            after.king_square(color).is_some()
+                && (after.king_square(!color).is_none() || !attacked(&after, color))
+        });
+        moves
+    }
+
+    fn play(&self, position: &Position, state: &State, m: ChessMove) -> (Position, State) {
+        let mut after = position.make_move(m);
+        if let Some(blast) = explosion(position, m) {
+            for square in blast {
+                after.clear_square(square);
+            }
+            after.update_castle_rights();
+        }
+        (after, *state)
+    }
+
+    fn in_check(&self, position: &Position, _state: &State) -> bool {
+        attacked(position, position.side_to_move())
+    }
+
+    fn outcome(&self, position: &Position, _state: &State) -> Option<GameResult> {
+        let mover = !position.side_to_move();
+        position
+            .king_square(!mover)
+            .is_none()
+            .then_some(GameResult::VariantWin { winner: mover })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chess::Square;
+
+    use super::*;
+
+    fn position(fen: &str) -> Position {
+        chess::Board::from_str(fen).unwrap().into()
+    }
+
+    #[test]
+    fn captures_explode_everything_but_pawns() {
+        let position = position("4k3/8/2n1p3/3r4/4b3/8/8/3QK3 w - - 0 1");
+        let m = ChessMove::new(Square::D1, Square::D5, None);
+        assert!(Atomic
+            .legal_moves(&position, &State::default())
+            .contains(&m));
+
+        let (after, _) = Atomic.play(&position, &State::default(), m);
+        for square in [Square::D1, Square::D5, Square::C6, Square::E4] {
+            assert_eq!(after.piece_on(square), None);
+        }
+        assert_eq!(after.piece_on(Square::E6), Some(Piece::Pawn));
+    }
+
+    #[test]
+    fn kings_cannot_capture() {
+        let position = position("4k3/8/8/8/8/8/4p3/4K3 w - - 0 1");
+        let m = ChessMove::new(Square::E1, Square::E2, None);
+        assert!(!Atomic
+            .legal_moves(&position, &State::default())
+            .contains(&m));
+    }
+
+    #[test]
+    fn blowing_up_the_king_wins() {
+        let position = position("4k3/3p4/8/8/8/8/8/3QK3 w - - 0 1");
+        let m = ChessMove::new(Square::D1, Square::D7, None);
+        assert!(Atomic
+            .legal_moves(&position, &State::default())
+            .contains(&m));
+
+        let (after, state) = Atomic.play(&position, &State::default(), m);
+        assert_eq!(after.king_square(Color::Black), None);
+        assert_eq!(
+            Atomic.outcome(&after, &state),
+            Some(GameResult::VariantWin {
+                winner: Color::White
+            })
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! Chess960 start positions and castling
+//!
+//! The `chess` crate only knows how to castle from the standard start squares, so in
+//! Chess960 games its castling rights are always empty and castling is handled here
+//! instead. Castling moves are written the way `UCI_Chess960` engines expect them:
+//! the king moves onto the square of the rook it castles with.
+
+use chess::{BoardBuilder, ChessMove, Color, File, MoveGen, Piece, Square};
+
+use super::{Position, Rules, State};
+
+/// Knight placements among the five squares left after placing bishops and queen
+const KNIGHTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+/// The number of the standard chess start position
+pub const STANDARD_POSITION: u16 = 518;
+
+/// Standard chess with castling handled by this module
+pub struct Chess960;
+
+impl Rules for Chess960 {
+    fn legal_moves(&self, position: &Position, state: &State) -> Vec<ChessMove> {
+        let board = position.chess();
+        let mut moves: Vec<ChessMove> = MoveGen::new_legal(&board).collect();
+        moves.extend(castling_moves(&board, &state.castling));
+        moves
+    }
+
+    fn play(&self, position: &Position, state: &State, m: ChessMove) -> (Position, State) {
+        let board = position.chess();
+        let mut state = *state;
+        if is_castle(&board, m) {
+            state.castling.0[board.side_to_move().to_index()] = [None; 2];
+            (castle(&board, m).into(), state)
+        } else {
+            state.castling.after_move(&board, m);
+            (board.make_move_new(m).into(), state)
+        }
+    }
+}
+
+/// Files of the rooks each side may still castle with, indexed by color then by
+/// kingside (0) and queenside (1)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CastlingRooks(pub [[Option<File>; 2]; 2]);
+
+impl CastlingRooks {
+    pub fn get(&self, color: Color) -> [Option<File>; 2] {
+        self.0[color.to_index()]
+    }
+
+    /// Updates the rights after a move that isn't castling
+    pub fn after_move(&mut self, board: &chess::Board, m: ChessMove) {
+        let color = board.side_to_move();
+        if board.piece_on(m.get_source()) == Some(Piece::King) {
+            self.0[color.to_index()] = [None; 2];
+        }
+        for side in chess::ALL_COLORS {
+            let back_rank = side.to_my_backrank();
+            for right in self.0[side.to_index()].iter_mut() {
+                let Some(file) = *right else {
+                    continue;
+                };
+                let rook = Square::make_square(back_rank, file);
+                if m.get_source() == rook || m.get_dest() == rook {
+                    *right = None;
+                }
+            }
+        }
+    }
+}
+
+/// The back rank of start position `number`, as numbered by Scharnagl
+pub fn back_rank(number: u16) -> [Piece; 8] {
+    let mut rank: [Option<Piece>; 8] = [None; 8];
+    let mut n = number as usize % 960;
+
+    rank[2 * (n % 4) + 1] = Some(Piece::Bishop);
+    n /= 4;
+    rank[2 * (n % 4)] = Some(Piece::Bishop);
+    n /= 4;
+
+    let empty: Vec<usize> = (0..8).filter(|&i| rank[i].is_none()).collect();
+    rank[empty[n % 6]] = Some(Piece::Queen);
+    n /= 6;
+
+    let empty: Vec<usize> = (0..8).filter(|&i| rank[i].is_none()).collect();
+    let (first, second) = KNIGHTS[n];
+    rank[empty[first]] = Some(Piece::Knight);
+    rank[empty[second]] = Some(Piece::Knight);
+
+    let empty: Vec<usize> = (0..8).filter(|&i| rank[i].is_none()).collect();
+    rank[empty[0]] = Some(Piece::Rook);
+    rank[empty[1]] = Some(Piece::King);
+    rank[empty[2]] = Some(Piece::Rook);
+
+    rank.map(|piece| piece.unwrap())
+}
+
+/// Sets up start position `number` with both sides able to castle either way
+pub fn start_position(number: u16) -> (chess::Board, CastlingRooks) {
+    let back_rank = back_rank(number);
+    let mut builder = BoardBuilder::new();
+    for color in chess::ALL_COLORS {
+        for (file, &piece) in chess::ALL_FILES.iter().zip(back_rank.iter()) {
+            builder.piece(
+                Square::make_square(color.to_my_backrank(), *file),
+                piece,
+                color,
+            );
+            builder.piece(
+                Square::make_square(color.to_second_rank(), *file),
+                Piece::Pawn,
+                color,
+            );
+        }
+    }
+    builder.side_to_move(Color::White);
+    let board = chess::Board::try_from(&builder).expect("Chess960 start positions are valid");
+
+    let rooks: Vec<File> = chess::ALL_FILES
+        .iter()
+        .zip(back_rank.iter())
+        .filter(|(_, &piece)| piece == Piece::Rook)
+        .map(|(&file, _)| file)
+        .collect();
+    let rights = [Some(rooks[1]), Some(rooks[0])];
+    (board, CastlingRooks([rights; 2]))
+}
+
+fn targets(color: Color, kingside: bool) -> (Square, Square) {
+    let rank = color.to_my_backrank();
+    if kingside {
+        (
+            Square::make_square(rank, File::G),
+            Square::make_square(rank, File::F),
+        )
+    } else {
+        (
+            Square::make_square(rank, File::C),
+            Square::make_square(rank, File::D),
+        )
+    }
+}
+
+/// The square the king ends up on after castling with `m`
+pub fn king_destination(color: Color, m: ChessMove) -> Square {
+    let kingside = m.get_dest().get_file().to_index() > m.get_source().get_file().to_index();
+    targets(color, kingside).0
+}
+
+/// The square the rook ends up on after castling with `m`
+pub fn rook_destination(color: Color, m: ChessMove) -> Square {
+    let kingside = m.get_dest().get_file().to_index() > m.get_source().get_file().to_index();
+    targets(color, kingside).1
+}
+
+/// True if `m` is the king moving onto one of its own rooks
+pub fn is_castle(board: &chess::Board, m: ChessMove) -> bool {
+    board.piece_on(m.get_source()) == Some(Piece::King)
+        && board.color_on(m.get_dest()) == Some(board.side_to_move())
+}
+
+/// Plays a castling move, assumed to be legal
+pub fn castle(board: &chess::Board, m: ChessMove) -> chess::Board {
+    let color = board.side_to_move();
+    let kingside = m.get_dest().get_file().to_index() > m.get_source().get_file().to_index();
+    let (king_to, rook_to) = targets(color, kingside);
+
+    let mut builder = BoardBuilder::from(*board);
+    builder
+        .clear_square(m.get_source())
+        .clear_square(m.get_dest())
+        .piece(king_to, Piece::King, color)
+        .piece(rook_to, Piece::Rook, color)
+        .side_to_move(!color)
+        .en_passant(None);
+    chess::Board::try_from(&builder).expect("castling should produce a valid position")
+}
+
+/// Every castling move available to the side to move
+pub fn castling_moves(board: &chess::Board, rights: &CastlingRooks) -> Vec<ChessMove> {
+    let color = board.side_to_move();
+    if board.checkers().popcnt() > 0 {
+        return Vec::new();
+    }
+
+    let king = board.king_square(color);
+    let rank = color.to_my_backrank();
+    let mut moves = Vec::new();
+    for (index, right) in rights.get(color).iter().enumerate() {
+        let Some(file) = *right else {
+            continue;
+        };
+        let rook = Square::make_square(rank, file);
+        if board.piece_on(rook) != Some(Piece::Rook) || board.color_on(rook) != Some(color) {
+            continue;
+        }
+
+        let (king_to, rook_to) = targets(color, index == 0);
+        let others = *board.combined()
+            ^ chess::BitBoard::from_square(king)
+            ^ chess::BitBoard::from_square(rook);
+        let king_path = chess::between(king, king_to) | chess::BitBoard::from_square(king_to);
+        let rook_path = chess::between(rook, rook_to) | chess::BitBoard::from_square(rook_to);
+        if (king_path | rook_path) & others != chess::EMPTY {
+            continue;
+        }
+        if king_path
+            .into_iter()
+            .any(|square| crate::game::attackers(board, square, !color).popcnt() > 0)
+        {
+            continue;
+        }
+
+        let m = ChessMove::new(king, rook, None);
+        let after = castle(board, m);
+        if crate::game::attackers(&after, king_to, !color).popcnt() > 0 {
+            continue;
+        }
+        moves.push(m);
+    }
+    moves
+}
+
+/// Writes the castling field of a FEN
+///
+/// Shredder-FEN always names the rook files. X-FEN uses `KQkq` unless another rook
+/// stands between the castling rook and the corner, where it falls back to the file.
+pub fn castling_field(board: &chess::Board, rights: &CastlingRooks, shredder: bool) -> String {
+    let mut field = String::new();
+    for color in chess::ALL_COLORS {
+        for (index, right) in rights.get(color).iter().enumerate() {
+            let Some(file) = *right else {
+                continue;
+            };
+            let outer = |f: usize| {
+                let square = Square::make_square(color.to_my_backrank(), File::from_index(f));
+                board.piece_on(square) == Some(Piece::Rook) && board.color_on(square) == Some(color)
+            };
+            let outermost = if index == 0 {
+                !(file.to_index() + 1..8).any(outer)
+            } else {
+                !(0..file.to_index()).any(outer)
+            };
+
+            let c = if shredder || !outermost {
+                (b'a' + file.to_index() as u8) as char
+            } else if index == 0 {
+                'k'
+            } else {
+                'q'
+            };
+            field.push(match color {
+                Color::White => c.to_ascii_uppercase(),
+                Color::Black => c,
+            });
+        }
+    }
+    if field.is_empty() {
+        field.push('-');
+    }
+    field
+}
+
+/// The FEN of `board` with Chess960 castling rights filled in
+pub fn fen(board: &chess::Board, rights: &CastlingRooks, shredder: bool) -> String {
+    let fen = board.to_string();
+    let mut fields: Vec<&str> = fen.split(' ').collect();
+    let castling = castling_field(board, rights, shredder);
+    if fields.len() > 2 {
+        fields[2] = &castling;
+    }
+    fields.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const STANDARD_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn numbering_follows_scharnagl() {
+        use Piece::*;
+        assert_eq!(
+            back_rank(0),
+            [Bishop, Bishop, Queen, Knight, Knight, Rook, King, Rook]
+        );
+        assert_eq!(
+            back_rank(STANDARD_POSITION),
+            [Rook, Knight, Bishop, Queen, King, Bishop, Knight, Rook]
+        );
+        assert_eq!(
+            back_rank(959),
+            [Rook, King, Rook, Knight, Knight, Queen, Bishop, Bishop]
+        );
+    }
+
+    #[test]
+    fn every_position_is_distinct_and_legal() {
+        let mut seen = std::collections::HashSet::new();
+        for number in 0..960 {
+            let rank = back_rank(number);
+            assert!(seen.insert(rank));
+
+            let find = |piece: Piece| (0..8).filter(move |&i| rank[i] == piece);
+            let bishops: Vec<usize> = find(Piece::Bishop).collect();
+            assert_ne!(bishops[0] % 2, bishops[1] % 2);
+            let rooks: Vec<usize> = find(Piece::Rook).collect();
+            let king = find(Piece::King).next().unwrap();
+            assert!(rooks[0] < king && king < rooks[1]);
+        }
+    }
+
+    #[test]
+    fn fen_of_the_standard_position() {
+        let (board, rights) = start_position(STANDARD_POSITION);
+        assert_eq!(fen(&board, &rights, false), STANDARD_FEN);
+        assert_eq!(
+            fen(&board, &rights, true),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+        );
+    }
+
+    #[test]
+    fn x_fen_names_rooks_that_are_not_outermost() {
+        let board = chess::Board::from_str("4k3/8/8/8/8/8/8/R1R1K2R w - - 0 1").unwrap();
+        let rights = CastlingRooks([[Some(File::H), Some(File::C)], [None, None]]);
+        assert_eq!(castling_field(&board, &rights, false), "KC");
+        assert_eq!(castling_field(&board, &rights, true), "HC");
+    }
+
+    #[test]
+    fn king_castles_onto_its_rook() {
+        let board =
+            chess::Board::from_str("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQK2R w - - 0 1").unwrap();
+        let (_, rights) = start_position(STANDARD_POSITION);
+        let m = ChessMove::new(Square::E1, Square::H1, None);
+        assert_eq!(castling_moves(&board, &rights), [m]);
+
+        let after = castle(&board, m);
+        assert_eq!(after.piece_on(Square::G1), Some(Piece::King));
+        assert_eq!(after.piece_on(Square::F1), Some(Piece::Rook));
+        assert_eq!(after.piece_on(Square::H1), None);
+    }
+}
@@ -0,0 +1,191 @@
+//! Crazyhouse: captured pieces change sides and can be dropped back onto the board
+//!
+//! `chess::ChessMove` has no room for drops, so a drop is written as a move from
+//! the target square onto itself, with the dropped piece in the promotion slot.
+//! No ordinary move can ever look like that.
+
+use chess::{BitBoard, ChessMove, File, MoveGen, Piece, Rank, Square, EMPTY};
+
+use super::{position::piece_from_char, Position, Rules, State};
+
+/// The pieces that can be held in a pocket, in the order they are shown
+pub const POCKET_PIECES: [Piece; 5] = [
+    Piece::Pawn,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Rook,
+    Piece::Queen,
+];
+
+/// The move dropping `piece` from the pocket onto `square`
+pub fn drop_move(piece: Piece, square: Square) -> ChessMove {
+    ChessMove::new(square, square, Some(piece))
+}
+
+/// The piece and target square of `m` if it is a drop
+pub fn as_drop(m: ChessMove) -> Option<(Piece, Square)> {
+    if m.get_source() == m.get_dest() {
+        m.get_promotion().map(|piece| (piece, m.get_dest()))
+    } else {
+        None
+    }
+}
+
+/// Marks the promoted pieces of a FEN placement with `~` and adds the pockets in
+/// brackets, as in `RNBQKBNR[Qn]`
+pub fn write_placement(placement: &str, state: &State) -> String {
+    let mut out = String::new();
+    for (row, rank) in placement.split('/').zip((0..8).rev()) {
+        if !out.is_empty() {
+            out.push('/');
+        }
+        let mut file = 0;
+        for c in row.chars() {
+            out.push(c);
+            if let Some(empty) = c.to_digit(10) {
+                file += empty as usize;
+                continue;
+            }
+            let square = Square::make_square(Rank::from_index(rank), File::from_index(file));
+            if state.promoted & BitBoard::from_square(square) != EMPTY {
+                out.push('~');
+            }
+            file += 1;
+        }
+    }
+
+    out.push('[');
+    for color in chess::ALL_COLORS {
+        for &piece in POCKET_PIECES.iter().rev() {
+            for _ in 0..state.in_pocket(color, piece) {
+                out.push_str(&piece.to_string(color));
+            }
+        }
+    }
+    out.push(']');
+    out
+}
+
+/// Splits a Crazyhouse FEN placement into the placement of the board and the
+/// pockets and promoted pieces it adds to `state`
+///
+/// The pockets may be written in brackets or as a ninth rank.
+pub fn read_placement(placement: &str, state: &mut State) -> Result<String, String> {
+    let (board, pockets) = match placement.split_once('[') {
+        Some((board, pockets)) => (
+            board,
+            pockets
+                .strip_suffix(']')
+                .ok_or_else(|| format!("Unclosed pocket in {}", placement))?,
+        ),
+        None => match placement.match_indices('/').nth(7) {
+            Some((index, _)) => (&placement[..index], &placement[index + 1..]),
+            None => (placement, ""),
+        },
+    };
+
+    for c in pockets.chars() {
+        let (piece, color) = piece_from_char(c)
+            .filter(|&(piece, _)| POCKET_PIECES.contains(&piece))
+            .ok_or_else(|| format!("Unknown pocket piece {}", c))?;
+        state.pockets[color.to_index()][piece.to_index()] += 1;
+    }
+
+    let mut out = String::new();
+    for (row, rank) in board.split('/').zip((0..8).rev()) {
+        if !out.is_empty() {
+            out.push('/');
+        }
+        let mut file = 0;
+        for c in row.chars() {
+            if c == '~' {
+                // marks the piece just read
+                let file = File::from_index(file.saturating_sub(1).min(7));
+                let square = Square::make_square(Rank::from_index(rank), file);
+                state.promoted |= BitBoard::from_square(square);
+                continue;
+            }
+            out.push(c);
+            file += c.to_digit(10).unwrap_or(1) as usize;
+        }
+    }
+    Ok(out)
+}
+
+pub struct Crazyhouse;
+
+impl Rules for Crazyhouse {
+    fn legal_moves(&self, position: &Position, state: &State) -> Vec<ChessMove> {
+        let board = position.chess();
+        let mut moves: Vec<ChessMove> = MoveGen::new_legal(&board).collect();
+        let color = board.side_to_move();
+
+        // a drop can never expose the king, so only checks limit where pieces may go
+        let checkers = *board.checkers();
+        let targets = match checkers.popcnt() {
+            0 => !*board.combined(),
+            1 => chess::between(board.king_square(color), checkers.to_square()),
+            _ => EMPTY,
+        };
+        let back_ranks = chess::get_rank(Rank::First) | chess::get_rank(Rank::Eighth);
+
+        for piece in POCKET_PIECES {
+            if state.in_pocket(color, piece) == 0 {
+                continue;
+            }
+            let squares = if piece == Piece::Pawn {
+                targets & !back_ranks
+            } else {
+                targets
+            };
+            moves.extend(squares.map(|square| drop_move(piece, square)));
+        }
+        moves
+    }
+
+    fn play(&self, position: &Position, state: &State, m: ChessMove) -> (Position, State) {
+        let board = position.chess();
+        let color = board.side_to_move();
+        let mut state = *state;
+
+        if let Some((piece, square)) = as_drop(m) {
+            state.pockets[color.to_index()][piece.to_index()] -= 1;
+            let mut after = *position;
+            after.set_piece(square, piece, color);
+            after.set_side_to_move(!color);
+            after.set_en_passant(None);
+            return (after, state);
+        }
+
+        let source = BitBoard::from_square(m.get_source());
+        let dest = BitBoard::from_square(m.get_dest());
+        let moving = board.piece_on(m.get_source());
+        let captured = if board.color_on(m.get_dest()) == Some(!color) {
+            board.piece_on(m.get_dest())
+        } else if moving == Some(Piece::Pawn)
+            && m.get_source().get_file() != m.get_dest().get_file()
+        {
+            // en passant
+            Some(Piece::Pawn)
+        } else {
+            None
+        };
+        if let Some(captured) = captured {
+            // promoted pieces go back into the pocket as pawns
+            let captured = if state.promoted & dest != EMPTY {
+                Piece::Pawn
+            } else {
+                captured
+            };
+            state.pockets[color.to_index()][captured.to_index()] += 1;
+        }
+
+        let was_promoted = state.promoted & source != EMPTY;
+        state.promoted &= !(source | dest);
+        if was_promoted || m.get_promotion().is_some() {
+            state.promoted |= dest;
+        }
+
+        (board.make_move_new(m).into(), state)
+    }
+}
@@ -0,0 +1,21 @@
+//! King of the Hill: bringing the king to one of the four center squares wins
+
+use chess::{BitBoard, EMPTY};
+
+use super::{Position, Rules, State};
+use crate::GameResult;
+
+/// d4, e4, d5 and e5
+const HILL: BitBoard = BitBoard(0x0000_0018_1800_0000);
+
+pub struct KingOfTheHill;
+
+impl Rules for KingOfTheHill {
+    fn outcome(&self, position: &Position, _state: &State) -> Option<GameResult> {
+        let mover = !position.side_to_move();
+        let king = position
+            .king_square(mover)
+            .map_or(EMPTY, BitBoard::from_square);
+        (king & HILL != EMPTY).then_some(GameResult::VariantWin { winner: mover })
+    }
+}
@@ -0,0 +1,186 @@
+//! Chess variants played on top of standard chess
+//!
+//! Each variant is a set of [`Rules`] layered over standard chess. Rules can
+//! restrict or extend the legal moves, change how a move is played and end the
+//! game before checkmate or stalemate are looked at. Whatever a variant has to
+//! remember beyond the [`Position`] itself is kept in [`State`], which the game
+//! tree stores next to every position.
+
+pub mod antichess;
+pub mod atomic;
+pub mod chess960;
+pub mod crazyhouse;
+pub mod king_of_the_hill;
+mod position;
+pub mod three_check;
+
+use chess::{BitBoard, ChessMove, MoveGen};
+
+use crate::GameResult;
+use chess960::CastlingRooks;
+pub use position::Position;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chess960,
+    KingOfTheHill,
+    ThreeCheck,
+    Crazyhouse,
+    Antichess,
+    Atomic,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 7] = [
+        Variant::Standard,
+        Variant::Chess960,
+        Variant::KingOfTheHill,
+        Variant::ThreeCheck,
+        Variant::Crazyhouse,
+        Variant::Antichess,
+        Variant::Atomic,
+    ];
+
+    /// The name shown in the main menu and written to the PGN `Variant` tag
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::Chess960 => "Chess960",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::ThreeCheck => "Three-check",
+            Variant::Crazyhouse => "Crazyhouse",
+            Variant::Antichess => "Antichess",
+            Variant::Atomic => "Atomic",
+        }
+    }
+
+    pub fn rules(self) -> &'static dyn Rules {
+        match self {
+            Variant::Standard => &Standard,
+            Variant::Chess960 => &chess960::Chess960,
+            Variant::KingOfTheHill => &king_of_the_hill::KingOfTheHill,
+            Variant::ThreeCheck => &three_check::ThreeCheck,
+            Variant::Crazyhouse => &crazyhouse::Crazyhouse,
+            Variant::Antichess => &antichess::Antichess,
+            Variant::Atomic => &atomic::Atomic,
+        }
+    }
+
+    /// The position a new game starts from
+    ///
+    /// `chess960_position` is only used by Chess960.
+    pub fn start_position(self, chess960_position: u16) -> (Position, State) {
+        match self {
+            Variant::Chess960 => {
+                let (board, castling) = chess960::start_position(chess960_position);
+                let state = State {
+                    castling,
+                    ..Default::default()
+                };
+                (board.into(), state)
+            }
+            Variant::Antichess => (antichess::start_position(), State::default()),
+            _ => (Position::default(), State::default()),
+        }
+    }
+
+    /// The FEN of a position in this variant, with the move counters left at their defaults
+    ///
+    /// Chess960 castling rights are written as X-FEN, or as Shredder-FEN if `shredder`
+    /// is set. Crazyhouse adds the pockets to the placement and Three-check adds the
+    /// checks given after the move counters.
+    pub fn fen(self, position: &Position, state: &State, shredder: bool) -> String {
+        match self {
+            Variant::Chess960 => chess960::fen(&position.chess(), &state.castling, shredder),
+            Variant::Crazyhouse => {
+                let fen = position.to_string();
+                let (placement, rest) = fen.split_once(' ').unwrap_or((&fen, ""));
+                format!("{} {}", crazyhouse::write_placement(placement, state), rest)
+            }
+            Variant::ThreeCheck => {
+                format!("{} {}", position, three_check::write_checks(state.checks))
+            }
+            _ => position.to_string(),
+        }
+    }
+
+    /// Reads a FEN as written by [`Variant::fen`]
+    ///
+    /// Only Antichess and Atomic positions may be missing a king. Chess960 castling
+    /// rights aren't read, since games are set up from their start position number.
+    pub fn parse_fen(self, fen: &str) -> Result<(Position, State), String> {
+        let mut state = State::default();
+        let mut fields: Vec<String> = fen.split_whitespace().map(String::from).collect();
+        if self == Variant::ThreeCheck {
+            if let Some(index) = fields.iter().skip(1).position(|f| f.contains('+')) {
+                let field = fields.remove(index + 1);
+                state.checks = three_check::read_checks(&field)
+                    .ok_or_else(|| format!("Unknown check counts {}", field))?;
+            }
+        }
+        if let (Variant::Crazyhouse, Some(placement)) = (self, fields.first_mut()) {
+            *placement = crazyhouse::read_placement(placement, &mut state)?;
+        }
+
+        let position: Position = fields.join(" ").parse()?;
+        let kings_optional = matches!(self, Variant::Antichess | Variant::Atomic);
+        if !kings_optional && position.to_board().is_none() {
+            return Err(format!("Not a legal {} position: {}", self.name(), fen));
+        }
+        Ok((position, state))
+    }
+}
+
+/// The parts of a position a variant tracks outside of [`Position`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct State {
+    /// Castling rights in Chess960 games
+    pub castling: CastlingRooks,
+    /// Checks given so far, indexed by the checking color
+    pub checks: [u8; 2],
+    /// Crazyhouse pockets, indexed by color then by piece
+    pub pockets: [[u8; 5]; 2],
+    /// Pieces that got onto the board by promotion, which go back into a pocket as pawns
+    pub promoted: BitBoard,
+}
+
+impl State {
+    /// How many of `piece` `color` holds in its Crazyhouse pocket
+    pub fn in_pocket(&self, color: chess::Color, piece: chess::Piece) -> u8 {
+        self.pockets[color.to_index()][piece.to_index()]
+    }
+}
+
+/// The rules of a variant
+///
+/// The defaults are those of standard chess, played through `chess::Board`.
+pub trait Rules {
+    /// Every legal move in the position
+    fn legal_moves(&self, position: &Position, _state: &State) -> Vec<ChessMove> {
+        MoveGen::new_legal(&position.chess()).collect()
+    }
+
+    /// Plays `m`, assumed to be legal, returning the resulting position
+    fn play(&self, position: &Position, state: &State, m: ChessMove) -> (Position, State) {
+        (position.chess().make_move_new(m).into(), *state)
+    }
+
+    /// True if the side to move is in check
+    fn in_check(&self, position: &Position, _state: &State) -> bool {
+        position.chess().checkers().popcnt() > 0
+    }
+
+    /// A result reached through the variant's own win conditions
+    ///
+    /// Checked after every move, before checkmate and stalemate.
+    fn outcome(&self, _position: &Position, _state: &State) -> Option<GameResult> {
+        None
+    }
+}
+
+/// Standard chess, leaving every rule at its default
+pub struct Standard;
+
+impl Rules for Standard {}
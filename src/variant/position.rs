@@ -0,0 +1,511 @@
+use std::{fmt, str::FromStr};
+
+use chess::{
+    BitBoard, BoardBuilder, CastleRights, ChessMove, Color, File, Piece, Rank, Square, ALL_COLORS,
+    ALL_PIECES, EMPTY,
+};
+
+/// A position that, unlike `chess::Board`, stays valid without one of the kings
+///
+/// Antichess and Atomic need this; the other variants always keep both kings and
+/// convert to a `chess::Board` with [`Position::chess`] to use its move generation.
+/// The accessors mirror those of `chess::Board`.
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pieces: [BitBoard; 6],
+    colors: [BitBoard; 2],
+    combined: BitBoard,
+    side_to_move: Color,
+    castle_rights: [CastleRights; 2],
+    /// The pawn that can be captured en passant, as in `chess::Board`
+    en_passant: Option<Square>,
+    /// The position as a `chess::Board` if it was made from one and hasn't changed
+    /// since, so standard move generation doesn't have to build it again
+    board: Option<chess::Board>,
+}
+
+/// Positions are equal when their pieces and rights are, whether or not they
+/// still hold a `chess::Board`
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        self.pieces == other.pieces
+            && self.colors == other.colors
+            && self.side_to_move == other.side_to_move
+            && self.castle_rights == other.castle_rights
+            && self.en_passant == other.en_passant
+    }
+}
+
+impl Eq for Position {}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::from(chess::Board::default())
+    }
+}
+
+impl From<chess::Board> for Position {
+    fn from(board: chess::Board) -> Self {
+        Self {
+            pieces: ALL_PIECES.map(|piece| *board.pieces(piece)),
+            colors: ALL_COLORS.map(|color| *board.color_combined(color)),
+            combined: *board.combined(),
+            side_to_move: board.side_to_move(),
+            castle_rights: ALL_COLORS.map(|color| board.castle_rights(color)),
+            en_passant: board.en_passant(),
+            board: Some(board),
+        }
+    }
+}
+
+impl Position {
+    /// Converts to a `chess::Board`, which fails if a king is missing
+    pub fn to_board(&self) -> Option<chess::Board> {
+        if self.board.is_some() {
+            return self.board;
+        }
+        let mut builder = BoardBuilder::new();
+        for square in self.combined {
+            builder.piece(square, self.piece_on(square)?, self.color_on(square)?);
+        }
+        builder
+            .side_to_move(self.side_to_move)
+            .castle_rights(Color::White, self.castle_rights[0])
+            .castle_rights(Color::Black, self.castle_rights[1])
+            .en_passant(self.en_passant.map(|square| square.get_file()));
+        chess::Board::try_from(&builder).ok()
+    }
+
+    /// The position as a `chess::Board`, for variants that never lose a king
+    pub fn chess(&self) -> chess::Board {
+        self.to_board()
+            .expect("position should be a valid chess position")
+    }
+
+    pub fn piece_on(&self, square: Square) -> Option<Piece> {
+        let square = BitBoard::from_square(square);
+        if self.combined & square == EMPTY {
+            return None;
+        }
+        ALL_PIECES
+            .into_iter()
+            .find(|piece| self.pieces[piece.to_index()] & square != EMPTY)
+    }
+
+    pub fn color_on(&self, square: Square) -> Option<Color> {
+        let square = BitBoard::from_square(square);
+        ALL_COLORS
+            .into_iter()
+            .find(|color| self.colors[color.to_index()] & square != EMPTY)
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.side_to_move
+    }
+
+    pub fn combined(&self) -> &BitBoard {
+        &self.combined
+    }
+
+    pub fn color_combined(&self, color: Color) -> &BitBoard {
+        &self.colors[color.to_index()]
+    }
+
+    pub fn pieces(&self, piece: Piece) -> &BitBoard {
+        &self.pieces[piece.to_index()]
+    }
+
+    pub fn set_side_to_move(&mut self, color: Color) {
+        self.side_to_move = color;
+        self.board = None;
+    }
+
+    pub fn castle_rights(&self, color: Color) -> CastleRights {
+        self.castle_rights[color.to_index()]
+    }
+
+    pub fn set_castle_rights(&mut self, color: Color, rights: CastleRights) {
+        self.castle_rights[color.to_index()] = rights;
+        self.board = None;
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    pub fn set_en_passant(&mut self, pawn: Option<Square>) {
+        self.en_passant = pawn;
+        self.board = None;
+    }
+
+    pub fn king_square(&self, color: Color) -> Option<Square> {
+        let kings = self.pieces[Piece::King.to_index()] & self.colors[color.to_index()];
+        (kings != EMPTY).then(|| kings.to_square())
+    }
+
+    /// The pieces of color `by` attacking `square`
+    pub fn attackers(&self, square: Square, by: Color) -> BitBoard {
+        let pieces = |piece: Piece| self.pieces[piece.to_index()];
+        let diagonal = pieces(Piece::Bishop) | pieces(Piece::Queen);
+        let straight = pieces(Piece::Rook) | pieces(Piece::Queen);
+        (chess::get_knight_moves(square) & pieces(Piece::Knight)
+            | chess::get_king_moves(square) & pieces(Piece::King)
+            | chess::get_bishop_moves(square, self.combined) & diagonal
+            | chess::get_rook_moves(square, self.combined) & straight
+            | chess::get_pawn_attacks(square, !by, pieces(Piece::Pawn)))
+            & self.colors[by.to_index()]
+    }
+
+    pub fn set_piece(&mut self, square: Square, piece: Piece, color: Color) {
+        self.clear_square(square);
+        let square = BitBoard::from_square(square);
+        self.pieces[piece.to_index()] |= square;
+        self.colors[color.to_index()] |= square;
+        self.combined |= square;
+    }
+
+    pub fn clear_square(&mut self, square: Square) {
+        let square = !BitBoard::from_square(square);
+        for pieces in self.pieces.iter_mut() {
+            *pieces &= square;
+        }
+        for colors in self.colors.iter_mut() {
+            *colors &= square;
+        }
+        self.combined &= square;
+        self.board = None;
+    }
+
+    pub fn is_en_passant(&self, m: ChessMove) -> bool {
+        self.piece_on(m.get_source()) == Some(Piece::Pawn)
+            && m.get_source().get_file() != m.get_dest().get_file()
+            && self.piece_on(m.get_dest()).is_none()
+    }
+
+    pub fn is_capture(&self, m: ChessMove) -> bool {
+        self.color_on(m.get_dest()) == Some(!self.side_to_move) || self.is_en_passant(m)
+    }
+
+    /// True if `m` is a pawn move or a capture, either of which resets the fifty move count
+    pub fn resets_fifty_moves(&self, m: ChessMove) -> bool {
+        super::crazyhouse::as_drop(m).is_none()
+            && (self.piece_on(m.get_source()) == Some(Piece::Pawn)
+                || self.color_on(m.get_dest()) == Some(!self.side_to_move))
+    }
+
+    /// Every move following the movement rules of the pieces, ignoring checks and castling
+    ///
+    /// Pawns reaching the last rank promote to each of `promotions`.
+    pub fn pseudo_legal_moves(&self, promotions: &[Piece]) -> Vec<ChessMove> {
+        let color = self.side_to_move;
+        let own = self.colors[color.to_index()];
+        let theirs = self.colors[(!color).to_index()];
+        let en_passant = self
+            .en_passant
+            .and_then(|pawn| pawn.forward(color))
+            .map_or(EMPTY, BitBoard::from_square);
+
+        let mut moves = Vec::new();
+        for source in own {
+            let piece = self.piece_on(source).unwrap();
+            let targets = match piece {
+                Piece::Pawn => {
+                    chess::get_pawn_quiets(source, color, self.combined)
+                        | chess::get_pawn_attacks(source, color, theirs | en_passant)
+                }
+                Piece::Knight => chess::get_knight_moves(source),
+                Piece::Bishop => chess::get_bishop_moves(source, self.combined),
+                Piece::Rook => chess::get_rook_moves(source, self.combined),
+                Piece::Queen => {
+                    chess::get_bishop_moves(source, self.combined)
+                        | chess::get_rook_moves(source, self.combined)
+                }
+                Piece::King => chess::get_king_moves(source),
+            } & !own;
+
+            for dest in targets {
+                if piece == Piece::Pawn && dest.get_rank() == color.to_their_backrank() {
+                    moves.extend(
+                        promotions
+                            .iter()
+                            .map(|&promotion| ChessMove::new(source, dest, Some(promotion))),
+                    );
+                } else {
+                    moves.push(ChessMove::new(source, dest, None));
+                }
+            }
+        }
+        moves
+    }
+
+    /// Plays `m` without checking that it is legal
+    ///
+    /// Castling is only recognized as the king moving two files from its standard
+    /// start square.
+    pub fn make_move(&self, m: ChessMove) -> Position {
+        let color = self.side_to_move;
+        let source = m.get_source();
+        let dest = m.get_dest();
+        let piece = self
+            .piece_on(source)
+            .expect("a piece should stand on the source square");
+
+        let mut after = *self;
+        after.board = None;
+        if let (true, Some(pawn)) = (self.is_en_passant(m), self.en_passant) {
+            after.clear_square(pawn);
+        }
+        after.clear_square(source);
+        after.set_piece(dest, m.get_promotion().unwrap_or(piece), color);
+
+        let files = dest.get_file().to_index() as i32 - source.get_file().to_index() as i32;
+        if piece == Piece::King && files.abs() == 2 {
+            let rank = source.get_rank();
+            let (from, to) = if files > 0 {
+                (File::H, File::F)
+            } else {
+                (File::A, File::D)
+            };
+            after.clear_square(Square::make_square(rank, from));
+            after.set_piece(Square::make_square(rank, to), Piece::Rook, color);
+        }
+
+        after.en_passant = None;
+        let ranks = dest.get_rank().to_index() as i32 - source.get_rank().to_index() as i32;
+        if piece == Piece::Pawn && ranks.abs() == 2 {
+            let neighbors =
+                chess::get_adjacent_files(dest.get_file()) & chess::get_rank(dest.get_rank());
+            let their_pawns =
+                self.pieces[Piece::Pawn.to_index()] & self.colors[(!color).to_index()];
+            if neighbors & their_pawns != EMPTY {
+                after.en_passant = Some(dest);
+            }
+        }
+
+        after.side_to_move = !color;
+        after.update_castle_rights();
+        after
+    }
+
+    /// Drops castling rights whose king or rook has left its start square
+    pub fn update_castle_rights(&mut self) {
+        for color in ALL_COLORS {
+            let rank = color.to_my_backrank();
+            let home = |piece: Piece, file: File| {
+                let square = Square::make_square(rank, file);
+                self.piece_on(square) == Some(piece) && self.color_on(square) == Some(color)
+            };
+            let mut rights = self.castle_rights[color.to_index()];
+            if !home(Piece::King, File::E) {
+                rights = CastleRights::NoRights;
+            }
+            if !home(Piece::Rook, File::H) {
+                rights = rights.remove(CastleRights::KingSide);
+            }
+            if !home(Piece::Rook, File::A) {
+                rights = rights.remove(CastleRights::QueenSide);
+            }
+            self.castle_rights[color.to_index()] = rights;
+        }
+        self.board = None;
+    }
+}
+
+/// The piece written as `c` in a FEN, white in uppercase and black in lowercase
+pub(super) fn piece_from_char(c: char) -> Option<(Piece, Color)> {
+    let piece = match c.to_ascii_lowercase() {
+        'p' => Piece::Pawn,
+        'n' => Piece::Knight,
+        'b' => Piece::Bishop,
+        'r' => Piece::Rook,
+        'q' => Piece::Queen,
+        'k' => Piece::King,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() {
+        Color::White
+    } else {
+        Color::Black
+    };
+    Some((piece, color))
+}
+
+/// Reads a FEN, which unlike with `chess::Board` may be missing a king
+///
+/// The move counters are ignored, and castling rights whose king or rook isn't on
+/// its start square are dropped.
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(fen: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let (Some(placement), Some(side)) = (fields.first(), fields.get(1)) else {
+            return Err(format!("Incomplete FEN: {}", fen));
+        };
+
+        let mut position = Position {
+            pieces: [EMPTY; 6],
+            colors: [EMPTY; 2],
+            combined: EMPTY,
+            side_to_move: Color::White,
+            castle_rights: [CastleRights::NoRights; 2],
+            en_passant: None,
+            board: None,
+        };
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("Expected 8 ranks in {}", placement));
+        }
+        for (rank, row) in ranks.into_iter().rev().enumerate() {
+            let mut file = 0;
+            for c in row.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    file += empty as usize;
+                    continue;
+                }
+                let (piece, color) =
+                    piece_from_char(c).ok_or_else(|| format!("Unknown piece {}", c))?;
+                if file >= 8 {
+                    return Err(format!("Rank {} has more than 8 squares", rank + 1));
+                }
+                let square = Square::make_square(Rank::from_index(rank), File::from_index(file));
+                position.set_piece(square, piece, color);
+                file += 1;
+            }
+            if file != 8 {
+                return Err(format!("Rank {} doesn't have 8 squares", rank + 1));
+            }
+        }
+
+        position.side_to_move = match *side {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(format!("Unknown side to move {}", side)),
+        };
+
+        for c in fields.get(2).unwrap_or(&"-").chars() {
+            let (color, right) = match c {
+                'K' => (Color::White, CastleRights::KingSide),
+                'Q' => (Color::White, CastleRights::QueenSide),
+                'k' => (Color::Black, CastleRights::KingSide),
+                'q' => (Color::Black, CastleRights::QueenSide),
+                '-' => continue,
+                _ => return Err(format!("Unknown castling right {}", c)),
+            };
+            let rights = &mut position.castle_rights[color.to_index()];
+            *rights = rights.add(right);
+        }
+        position.update_castle_rights();
+
+        if let Some(&target) = fields.get(3).filter(|&&field| field != "-") {
+            let target =
+                Square::from_str(target).map_err(|_| format!("Unknown square {}", target))?;
+            // the FEN names the square behind the pawn that can be taken
+            let pawn = target.backward(position.side_to_move);
+            let theirs = Some(!position.side_to_move);
+            position.en_passant = pawn.filter(|&pawn| {
+                position.piece_on(pawn) == Some(Piece::Pawn) && position.color_on(pawn) == theirs
+            });
+        }
+
+        position.board = position.to_board();
+        Ok(position)
+    }
+}
+
+/// Writes the position as FEN, with the move counters left at their defaults
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                let square = Square::make_square(Rank::from_index(rank), File::from_index(file));
+                match (self.piece_on(square), self.color_on(square)) {
+                    (Some(piece), Some(color)) => {
+                        if empty > 0 {
+                            write!(f, "{}", empty)?;
+                            empty = 0;
+                        }
+                        write!(f, "{}", piece.to_string(color))?;
+                    }
+                    _ => empty += 1,
+                }
+            }
+            if empty > 0 {
+                write!(f, "{}", empty)?;
+            }
+            if rank > 0 {
+                write!(f, "/")?;
+            }
+        }
+
+        let side = match self.side_to_move {
+            Color::White => "w",
+            Color::Black => "b",
+        };
+        let mut castling = String::new();
+        for (color, kingside, queenside) in [(Color::White, 'K', 'Q'), (Color::Black, 'k', 'q')] {
+            let rights = self.castle_rights(color);
+            if rights.has_kingside() {
+                castling.push(kingside);
+            }
+            if rights.has_queenside() {
+                castling.push(queenside);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        let en_passant = self
+            .en_passant
+            .and_then(|pawn| pawn.forward(self.side_to_move))
+            .map_or(String::from("-"), |square| square.to_string());
+        write!(f, " {} {} {} 0 1", side, castling, en_passant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 1",
+            "8/8/8/3p4/8/8/8/R7 w - - 0 1",
+        ] {
+            let position: Position = fen.parse().unwrap();
+            assert_eq!(position.to_string(), fen);
+        }
+    }
+
+    #[test]
+    fn positions_missing_a_king_are_read() {
+        let position: Position = "8/8/8/3p4/8/8/8/4K3 b - - 0 1".parse().unwrap();
+        assert_eq!(position.king_square(Color::Black), None);
+        assert_eq!(position.king_square(Color::White), Some(Square::E1));
+        assert_eq!(position.piece_on(Square::D5), Some(Piece::Pawn));
+        assert_eq!(position.to_board(), None);
+    }
+
+    #[test]
+    fn matches_chess_board() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let position: Position = fen.parse().unwrap();
+        assert_eq!(
+            position,
+            Position::from(chess::Board::from_str(fen).unwrap())
+        );
+        assert!(position.to_board().is_some());
+    }
+
+    #[test]
+    fn bad_fens_are_rejected() {
+        assert!("".parse::<Position>().is_err());
+        assert!("8/8/8/8/8/8/8 w - - 0 1".parse::<Position>().is_err());
+        assert!("8/8/8/8/8/8/8/9 w - - 0 1".parse::<Position>().is_err());
+        assert!("8/8/8/8/8/8/8/x7 w - - 0 1".parse::<Position>().is_err());
+        assert!("8/8/8/8/8/8/8/8 x - - 0 1".parse::<Position>().is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Three-check: the side giving the third check wins
+
+use chess::ChessMove;
+
+use super::{Position, Rules, State};
+use crate::GameResult;
+
+pub const CHECKS_TO_WIN: u8 = 3;
+
+/// The checks given so far as written after a FEN, such as `+1+0`
+pub fn write_checks(checks: [u8; 2]) -> String {
+    format!("+{}+{}", checks[0], checks[1])
+}
+
+/// Reads the checks given by each side from a FEN field
+///
+/// Both `+1+0`, counting checks given, and `2+3`, counting checks left to give, are
+/// understood.
+pub fn read_checks(field: &str) -> Option<[u8; 2]> {
+    let (given, counts) = match field.strip_prefix('+') {
+        Some(counts) => (true, counts),
+        None => (false, field),
+    };
+    let (white, black) = counts.split_once('+')?;
+    let count = |n: &str| {
+        let n: u8 = n.parse().ok()?;
+        if given {
+            Some(n)
+        } else {
+            CHECKS_TO_WIN.checked_sub(n)
+        }
+    };
+    Some([count(white)?, count(black)?])
+}
+
+pub struct ThreeCheck;
+
+impl Rules for ThreeCheck {
+    fn play(&self, position: &Position, state: &State, m: ChessMove) -> (Position, State) {
+        let after = position.chess().make_move_new(m);
+        let mut state = *state;
+        if after.checkers().popcnt() > 0 {
+            state.checks[position.side_to_move().to_index()] += 1;
+        }
+        (after.into(), state)
+    }
+
+    fn outcome(&self, position: &Position, state: &State) -> Option<GameResult> {
+        let mover = !position.side_to_move();
+        (state.checks[mover.to_index()] >= CHECKS_TO_WIN)
+            .then_some(GameResult::VariantWin { winner: mover })
+    }
+}
@@ -127,6 +127,7 @@ fn wasm_deploy() -> anyhow::Result<()> {
     println!("xtask/wasm-deploy => Copying assets to Dropbox...");
     let home_dir = dirs::home_dir().context("couldn't get path to home dir")?;
     Command::new("cp").args([
+        "-r",
         "assets/*",
         home_dir
             .join("Dropbox/website/website-assets/shake-chess")